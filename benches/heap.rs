@@ -0,0 +1,69 @@
+#![feature(test)]
+
+extern crate french_press;
+extern crate jsrs_common;
+extern crate js_types;
+extern crate test;
+
+use std::cell::RefCell;
+use std::collections::hash_set::HashSet;
+use std::rc::Rc;
+
+use french_press::{DeclKind, ScopeManager, ScopeManagerBuilder};
+use french_press::alloc::{AllocBox, Heap, SlabAllocBox};
+use jsrs_common::ast::Exp;
+use js_types::binding::Binding;
+use js_types::js_str::JsStrStruct;
+use js_types::js_var::{JsPtrEnum, JsPtrTag, JsType, JsVar};
+use test::Bencher;
+
+const ALLOCS: usize = 1000;
+const SCOPES: usize = 100;
+
+fn make_str() -> JsPtrEnum {
+    JsPtrEnum::JsStr(JsStrStruct::new("x"))
+}
+
+// Allocate `ALLOCS` strings, root every other one, and collect the rest, then
+// collect the survivors.
+fn alloc_and_collect<H: Heap>(heap: &mut H) {
+    let mut roots = HashSet::new();
+    for i in 0..ALLOCS {
+        let bnd = Binding::anon();
+        heap.alloc(bnd.clone(), make_str()).unwrap();
+        if i % 2 == 0 { roots.insert(bnd); }
+    }
+    heap.collect(&roots);
+    heap.collect(&HashSet::new());
+}
+
+// Push `SCOPES` block scopes, allocating a string in each, and collect as each
+// is popped.
+fn scope_churn<H: Heap>(mut mgr: ScopeManager<H>) {
+    for _ in 0..SCOPES {
+        mgr.push_scope(&Exp::Undefined);
+        let var = JsVar::new(JsType::JsPtr(JsPtrTag::JsStr));
+        mgr.alloc(var, Some(make_str()), DeclKind::Let).unwrap();
+        mgr.pop_scope(true).unwrap();
+    }
+}
+
+#[bench]
+fn bench_alloc_box_collect(b: &mut Bencher) {
+    b.iter(|| alloc_and_collect(&mut AllocBox::new()));
+}
+
+#[bench]
+fn bench_slab_collect(b: &mut Bencher) {
+    b.iter(|| alloc_and_collect(&mut SlabAllocBox::new()));
+}
+
+#[bench]
+fn bench_alloc_box_scopes(b: &mut Bencher) {
+    b.iter(|| scope_churn(ScopeManagerBuilder::new().build()));
+}
+
+#[bench]
+fn bench_slab_scopes(b: &mut Bencher) {
+    b.iter(|| scope_churn(ScopeManagerBuilder::new().build_on(Rc::new(RefCell::new(SlabAllocBox::new())))));
+}
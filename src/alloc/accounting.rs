@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+use std::io::{self, Write};
+use std::rc::{Rc, Weak};
+
+use js_types::js_var::JsPtrEnum;
+use js_types::binding::Binding;
+
+use super::{forwarded, GcConfig, GcPolicy, GcStats};
+use super::stats::CycleStats;
+
+/// The bookkeeping shared by every heap engine: the `GcPolicy` that requests
+/// collections and enforces hard limits, the collector's statistics, and the
+/// root sets registered with the heap. The engine owns the allocations and
+/// does its own marking and sweeping, telling the accounting how many objects
+/// it holds and how many bytes they take up as they change.
+pub struct Accounting {
+    policy: GcPolicy,
+    // Estimated size of every live allocation, in bytes.
+    bytes: usize,
+    // Collection triggers, recomputed by the policy after every collection.
+    object_limit: usize,
+    byte_limit: usize,
+    allocs_since_gc: usize,
+    gc_requested: bool,
+    trace: bool,
+    stats: GcStats,
+    // Whether per-cycle metrics are recorded in `stats.cycles`.
+    record_cycles: bool,
+    // The root sets of the scopes of every manager sharing the heap.
+    root_sets: Vec<Weak<RefCell<HashSet<Binding>>>>,
+    // The number of collections run, which unlike the stats is never reset.
+    sweeps: usize,
+}
+
+impl Accounting {
+    pub fn new() -> Accounting {
+        let policy = GcPolicy::default();
+        Accounting {
+            object_limit: policy.object_threshold,
+            byte_limit: policy.byte_threshold,
+            policy: policy,
+            bytes: 0,
+            allocs_since_gc: 0,
+            gc_requested: false,
+            trace: false,
+            stats: GcStats::default(),
+            record_cycles: false,
+            root_sets: Vec::new(),
+            sweeps: 0,
+        }
+    }
+
+    /// Apply the parts of a configuration that don't depend on the engine.
+    pub fn configure(&mut self, config: &GcConfig, len: usize) {
+        self.trace = config.trace;
+        self.record_cycles = config.stats;
+        self.set_policy(config.policy.clone(), len);
+    }
+
+    pub fn policy(&self) -> &GcPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: GcPolicy, len: usize) {
+        self.policy = policy;
+        self.reset_triggers(len);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn gc_requested(&self) -> bool {
+        self.gc_requested
+    }
+
+    /// Whether a heap of `len` objects has room for `objects` more allocations
+    /// totalling `bytes` within the hard limits of the policy.
+    pub fn has_room(&self, len: usize, objects: usize, bytes: usize) -> bool {
+        self.policy.max_objects.map_or(true, |max| len + objects <= max) &&
+            self.policy.max_bytes.map_or(true, |max| self.bytes + bytes <= max)
+    }
+
+    /// Account for a new allocation of `size` bytes, leaving the heap with
+    /// `len` objects, and request a collection if the policy's limits are
+    /// exceeded.
+    pub fn allocated(&mut self, len: usize, size: usize) {
+        self.bytes += size;
+        self.update_peaks(len);
+        self.allocs_since_gc += 1;
+        if self.allocs_since_gc >= self.policy.min_interval &&
+           (len > self.object_limit || self.bytes > self.byte_limit) {
+            self.gc_requested = true;
+        }
+    }
+
+    /// Account for an allocation that changed size from `old_size` to
+    /// `new_size` bytes.
+    pub fn resized(&mut self, len: usize, old_size: usize, new_size: usize) {
+        self.bytes = self.bytes.saturating_sub(old_size) + new_size;
+        self.update_peaks(len);
+    }
+
+    /// Account for `size` bytes of allocations that were freed.
+    pub fn freed(&mut self, size: usize) {
+        self.bytes = self.bytes.saturating_sub(size);
+    }
+
+    /// Register a root set that every collection of the heap starts from, on
+    /// top of the roots it is given, until the set is dropped.
+    pub fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>) {
+        if self.root_sets.len() == self.root_sets.capacity() {
+            self.root_sets.retain(|set| set.upgrade().is_some());
+        }
+        self.root_sets.push(Rc::downgrade(roots));
+    }
+
+    /// The given roots, plus those of every registered root set still alive.
+    pub fn all_roots(&mut self, roots: &HashSet<Binding>) -> HashSet<Binding> {
+        self.root_sets.retain(|set| set.upgrade().is_some());
+        let mut all = roots.clone();
+        for set in &self.root_sets {
+            if let Some(set) = set.upgrade() {
+                all.extend(set.borrow().iter().cloned());
+            }
+        }
+        all
+    }
+
+    /// Point every registered root set at the bindings its roots were moved
+    /// to, so that the roots of other managers sharing the heap stay valid.
+    pub fn remap_roots(&self, forwarding: &HashMap<Binding, Binding>) {
+        for set in self.root_sets.iter().filter_map(|set| set.upgrade()) {
+            let moved: HashSet<Binding> = set.borrow()
+                                             .iter()
+                                             .map(|bnd| forwarded(forwarding, bnd))
+                                             .collect();
+            *set.borrow_mut() = moved;
+        }
+    }
+
+    /// Record a finished collection that left `len` objects on the heap.
+    pub fn record_cycle(&mut self, len: usize, cycle: CycleStats) {
+        self.sweeps += 1;
+        self.stats.collections += 1;
+        if self.trace {
+            let _ = writeln!(io::stderr(), "gc: {:?} collection marked {} and swept {} objects \
+                                            in {:?} + {:?}, {} live ({} bytes)",
+                             cycle.kind, cycle.marked, cycle.swept, cycle.mark_time,
+                             cycle.sweep_time, len, self.bytes);
+        }
+        if self.record_cycles {
+            self.stats.cycles.push(cycle);
+        }
+    }
+
+    /// Clear any pending collection request and set the next limits from the
+    /// size of the heap that survived.
+    pub fn reset_triggers(&mut self, len: usize) {
+        self.allocs_since_gc = 0;
+        self.gc_requested = false;
+        self.object_limit = self.policy.next_limit(len, self.policy.object_threshold);
+        self.byte_limit = self.policy.next_limit(self.bytes, self.policy.byte_threshold);
+    }
+
+    /// A snapshot of the statistics, with live object counts taken from the
+    /// given allocations.
+    pub fn stats<'a, I>(&self, allocs: I) -> GcStats
+        where I: IntoIterator<Item=&'a RefCell<JsPtrEnum>>
+    {
+        let mut stats = self.stats.clone();
+        for ptr in allocs {
+            stats.live.count(&*ptr.borrow());
+        }
+        stats
+    }
+
+    /// Clear all statistics. Peaks restart from the current heap size.
+    pub fn reset_stats(&mut self, len: usize) {
+        self.stats = GcStats::default();
+        self.update_peaks(len);
+    }
+
+    pub fn collections(&self) -> usize {
+        self.stats.collections
+    }
+
+    pub fn sweeps(&self) -> usize {
+        self.sweeps
+    }
+
+    fn update_peaks(&mut self, len: usize) {
+        if len > self.stats.peak_objects { self.stats.peak_objects = len; }
+        if self.bytes > self.stats.peak_bytes { self.stats.peak_bytes = self.bytes; }
+    }
+}
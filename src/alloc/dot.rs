@@ -0,0 +1,43 @@
+use std::fmt::{self, Write};
+
+use js_types::js_var::JsPtrEnum;
+use js_types::binding::Binding;
+
+use super::{Color, Heap};
+
+/// Quote a string for use as a Graphviz ID or label.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+/// The Graphviz node ID of a heap allocation.
+pub fn heap_node(bnd: &Binding) -> String {
+    quote(&format!("heap:{}", bnd))
+}
+
+/// Write every allocation of `heap` as a Graphviz node, filled with its
+/// current color, along with an edge to each of its children.
+pub fn write_heap<H: Heap>(heap: &H, out: &mut String) -> fmt::Result {
+    for (bnd, alloc) in heap.entries() {
+        let kind = match *alloc.borrow() {
+            JsPtrEnum::JsStr(_) => "string",
+            JsPtrEnum::JsObj(_) => "object",
+            JsPtrEnum::JsFn(_) => "function",
+            _ => "pointer",
+        };
+        let (fill, font) = match heap.color_of(bnd) {
+            Some(Color::Black) => ("black", "white"),
+            Some(Color::Grey) => ("grey", "black"),
+            Some(Color::Young) => ("lightblue", "black"),
+            _ => ("white", "black"),
+        };
+        try!(writeln!(out, "    {} [shape=ellipse, style=filled, fillcolor={}, fontcolor={}, label={}];",
+                      heap_node(bnd), fill, font, quote(&format!("{}\n{}", kind, bnd))));
+        for child in heap.children(bnd) {
+            if heap.find(&child).is_some() {
+                try!(writeln!(out, "    {} -> {};", heap_node(bnd), heap_node(&child)));
+            }
+        }
+    }
+    Ok(())
+}
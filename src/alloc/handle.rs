@@ -0,0 +1,102 @@
+use std::cell::{Ref, RefCell, RefMut};
+
+use js_types::js_var::JsPtrEnum;
+use js_types::binding::Binding;
+
+use gc_error::Result;
+
+use super::{AllocBox, Heap};
+use super::policy::size_of_ptr;
+
+// The allocation bound to `binding` in a borrowed heap.
+fn find<'a, H: Heap>(heap: &'a H, binding: &Binding) -> &'a RefCell<JsPtrEnum> {
+    match heap.find(binding) {
+        Some(alloc) => alloc,
+        None => panic!("The allocation of a live handle was freed: {}", binding),
+    }
+}
+
+/// A shared handle to a heap allocation, for reading it in place instead of
+/// copying it out of the heap. A handle borrows the heap, so nothing can be
+/// allocated, stored or collected until it is dropped. On a heap shared by
+/// several managers, this holds for all of them: doing so through any other
+/// manager while a handle is held panics, since the heap is already borrowed.
+pub struct GcRef<'a, H: 'a + Heap = AllocBox> {
+    heap: Ref<'a, H>,
+    binding: Binding,
+}
+
+impl<'a, H: Heap> GcRef<'a, H> {
+    pub fn new(heap: &'a RefCell<H>, binding: Binding) -> GcRef<'a, H> {
+        GcRef {
+            heap: heap.borrow(),
+            binding: binding,
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<JsPtrEnum> {
+        find(&*self.heap, &self.binding).borrow()
+    }
+}
+
+/// A mutable handle to a heap allocation, for updating it in place instead of
+/// copying it out and storing it back. Like a `GcRef`, it borrows the heap, so
+/// no other manager sharing it may allocate, store or collect while it is
+/// held, on pain of a panic. The write barrier, and the heap's size
+/// accounting, run when it is finished or dropped. Growth past the hard limits
+/// of the heap's `GcPolicy` is only reported by `finish`.
+pub struct GcRefMut<'a, H: 'a + Heap = AllocBox> {
+    cell: &'a RefCell<H>,
+    heap: Option<Ref<'a, H>>,
+    binding: Binding,
+    old_size: usize,
+}
+
+impl<'a, H: Heap> GcRefMut<'a, H> {
+    pub fn new(heap: &'a RefCell<H>, binding: Binding) -> GcRefMut<'a, H> {
+        let borrowed = heap.borrow();
+        let old_size = size_of_ptr(&*find(&*borrowed, &binding).borrow());
+        GcRefMut {
+            cell: heap,
+            heap: Some(borrowed),
+            binding: binding,
+            old_size: old_size,
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<JsPtrEnum> {
+        self.alloc().borrow()
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<JsPtrEnum> {
+        self.alloc().borrow_mut()
+    }
+
+    /// Release the handle, returning `GcError::OutOfMemory` if the mutation
+    /// grew the heap past its hard limits. The mutation is kept either way.
+    pub fn finish(mut self) -> Result<()> {
+        self.release()
+    }
+
+    // Release our borrow of the heap before updating it. Does nothing once
+    // the handle has been released.
+    fn release(&mut self) -> Result<()> {
+        if self.heap.take().is_none() {
+            return Ok(());
+        }
+        self.cell.borrow_mut().mutated(&self.binding, self.old_size)
+    }
+
+    fn alloc(&self) -> &RefCell<JsPtrEnum> {
+        match self.heap {
+            Some(ref heap) => find(&**heap, &self.binding),
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<'a, H: 'a + Heap> Drop for GcRefMut<'a, H> {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
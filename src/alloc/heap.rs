@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use gc_error::{GcError, Result};
+use js_types::js_var::JsPtrEnum;
+use js_types::allocator::Allocator;
+use js_types::binding::Binding;
+use verify::VerifyReport;
+
+use super::{dot, snapshot, Color, GcConfig, GcStats};
+
+/// A heap storage engine. `Scope` and `ScopeManager` only reach the heap
+/// through this trait, so either `AllocBox` or `SlabAllocBox` can back them.
+/// An engine without some feature of the collector, e.g. incremental marking,
+/// still implements its methods by falling back to what it does support; each
+/// engine documents its own gaps.
+pub trait Heap: Allocator<Error=GcError> + Sized {
+    /// The allocation bound to `bnd`, if any.
+    fn find(&self, bnd: &Binding) -> Option<&RefCell<JsPtrEnum>>;
+
+    /// Every allocation in the heap.
+    fn entries(&self) -> Vec<(&Binding, &RefCell<JsPtrEnum>)>;
+
+    /// The bindings the allocation bound to `bnd` points to: an object's
+    /// properties, or the environment a function closes over.
+    fn children(&self, bnd: &Binding) -> HashSet<Binding>;
+
+    /// Where an allocation currently sits in the collector, if it exists.
+    fn color_of(&self, bnd: &Binding) -> Option<Color>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The estimated size of the heap in bytes.
+    fn byte_len(&self) -> usize;
+
+    /// Replace the value of an allocation.
+    fn update_ptr(&mut self, binding: &Binding, ptr: JsPtrEnum) -> Result<()>;
+
+    /// Account for an allocation that was mutated in place, given its size
+    /// before the mutation.
+    fn mutated(&mut self, binding: &Binding, old_size: usize) -> Result<()>;
+
+    /// Make sure a binding that just became a root survives the collection
+    /// cycle in progress, if any.
+    fn shade(&mut self, binding: &Binding);
+
+    /// Record the heap bindings that a function's closure environment captures.
+    fn set_fn_env(&mut self, fn_bnd: Binding, env: HashSet<Binding>);
+
+    /// Make every closure environment that captures `old` capture `new` instead.
+    fn recapture(&mut self, old: &Binding, new: &Binding);
+
+    /// Register a root set that every collection starts from, until it is dropped.
+    fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>);
+
+    /// Run a full stop-the-world collection.
+    fn collect(&mut self, roots: &HashSet<Binding>);
+
+    /// Collect whichever generation is due.
+    fn collect_garbage(&mut self, roots: &HashSet<Binding>);
+
+    /// Begin an incremental collection cycle.
+    fn start_cycle(&mut self, roots: &HashSet<Binding>);
+
+    /// Do at most `budget` units of marking work, returning true once the
+    /// cycle is ready to sweep.
+    fn mark_step(&mut self, budget: usize) -> bool;
+
+    /// Finish and sweep the current cycle.
+    fn finish_cycle(&mut self);
+
+    /// Whether the heap's `GcPolicy` has asked for a collection.
+    fn gc_requested(&self) -> bool;
+
+    /// Whether `objects` more allocations totalling `bytes` would fit within
+    /// the hard limits of the heap's `GcPolicy`.
+    fn has_room(&self, objects: usize, bytes: usize) -> bool;
+
+    /// The number of collections run, which is never reset.
+    fn sweeps(&self) -> usize;
+
+    /// The bindings the last compacting collection moved allocations from,
+    /// mapped to the bindings it moved them to. Engines that never move an
+    /// allocation to a new binding have none.
+    fn forwarding(&self) -> Option<&HashMap<Binding, Binding>> {
+        None
+    }
+
+    fn configure(&mut self, config: GcConfig);
+
+    fn stats(&self) -> GcStats;
+
+    fn reset_stats(&mut self);
+
+    /// Check the heap's own invariants.
+    fn verify_into(&self, report: &mut VerifyReport);
+
+    /// Write every heap allocation as a Graphviz node, filled with its current
+    /// color, along with an edge to each of its children.
+    fn write_dot(&self, out: &mut String) -> fmt::Result {
+        dot::write_heap(self, out)
+    }
+
+    /// Serialise the heap in the V8 `.heapsnapshot` format. See
+    /// `ScopeManager::write_heap_snapshot`.
+    fn write_heap_snapshot<W: Write>(&self, roots: &[(String, Binding)], out: &mut W) -> io::Result<()> {
+        snapshot::write_heap_snapshot(self, roots, out)
+    }
+}
@@ -1,33 +1,197 @@
+mod accounting;
+pub mod dot;
+mod handle;
+mod heap;
+mod nursery;
+pub mod policy;
+mod snapshot;
+pub mod slab;
+pub mod stats;
+mod weak;
+
 use std::cell::RefCell;
-use std::collections::hash_map::{Entry, HashMap};
+use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
+use std::mem;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use gc_error::{GcError, Result};
 use js_types::js_var::JsPtrEnum;
 use js_types::allocator::Allocator;
 use js_types::binding::Binding;
+use verify::{Violation, VerifyReport};
+
+use self::accounting::Accounting;
+use self::nursery::Nursery;
+use self::policy::size_of_ptr;
+use self::stats::{CollectionKind, CycleStats};
+use self::weak::WeakTable;
+
+pub use self::handle::{GcRef, GcRefMut};
+pub use self::heap::Heap;
+pub use self::policy::{GcConfig, GcPolicy};
+pub use self::slab::SlabAllocBox;
+pub use self::stats::GcStats;
 
 pub type Alloc<T> = Rc<RefCell<T>>;
 
+/// Where an allocation currently sits in the collector: one of the three
+/// tri-color sets of the old generation, or the nursery.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Color {
+    White,
+    Grey,
+    Black,
+    Young,
+}
+
 pub struct AllocBox {
     black_set: HashMap<Binding, Alloc<JsPtrEnum>>,
     grey_set: HashMap<Binding, Alloc<JsPtrEnum>>,
     white_set: HashMap<Binding, Alloc<JsPtrEnum>>,
+    // True while an incremental cycle is between `start_cycle` and its sweep.
+    marking: bool,
+    // The young generation, if this heap is generational.
+    nursery: Option<Nursery>,
+    // The policy, statistics and registered root sets.
+    accounting: Accounting,
+    // Marking time accumulated by the steps of the current incremental cycle.
+    cycle_mark_time: Duration,
+    // The heap bindings captured by each function's closure environment.
+    fn_envs: HashMap<Binding, HashSet<Binding>>,
+    // Whether full and incremental collections end by compacting the heap.
+    compact: bool,
+    // The bindings the last compacting collection moved allocations from,
+    // mapped to the bindings it moved them to.
+    forwarding: HashMap<Binding, Binding>,
+    // Weak references and WeakMap entries, which are not traced.
+    weak: WeakTable,
 }
 
 impl Allocator for AllocBox {
     type Error = GcError;
 
     fn alloc(&mut self, binding: Binding, ptr: JsPtrEnum) -> Result<()> {
-        if let None = self.white_set.insert(binding.clone(), Rc::new(RefCell::new(ptr))) {
-            Ok(())
-        } else {
+        if self.find_id(&binding).is_some() {
             // If a binding already exists and we try to allocate it, this should
             // be an unrecoverable error.
-            Err(GcError::Alloc(binding))
+            return Err(GcError::Alloc(binding));
+        }
+        let size = size_of_ptr(&ptr);
+        if !self.has_room(1, size) {
+            return Err(GcError::OutOfMemory);
+        }
+        self.insert_fresh(binding, Rc::new(RefCell::new(ptr)));
+        let len = self.len();
+        self.accounting.allocated(len, size);
+        Ok(())
+    }
+}
+
+impl Heap for AllocBox {
+    fn find(&self, bnd: &Binding) -> Option<&RefCell<JsPtrEnum>> {
+        self.find_id(bnd).map(|alloc| &**alloc)
+    }
+
+    fn entries(&self) -> Vec<(&Binding, &RefCell<JsPtrEnum>)> {
+        self.allocations().into_iter().map(|(bnd, alloc)| (bnd, &**alloc)).collect()
+    }
+
+    fn children(&self, bnd: &Binding) -> HashSet<Binding> {
+        match self.find_id(bnd) {
+            Some(ptr) => self.children_of(bnd, ptr),
+            None => HashSet::new(),
         }
     }
+
+    fn color_of(&self, bnd: &Binding) -> Option<Color> {
+        AllocBox::color_of(self, bnd)
+    }
+
+    fn len(&self) -> usize {
+        AllocBox::len(self)
+    }
+
+    fn byte_len(&self) -> usize {
+        AllocBox::byte_len(self)
+    }
+
+    fn update_ptr(&mut self, binding: &Binding, ptr: JsPtrEnum) -> Result<()> {
+        AllocBox::update_ptr(self, binding, ptr)
+    }
+
+    fn mutated(&mut self, binding: &Binding, old_size: usize) -> Result<()> {
+        AllocBox::mutated(self, binding, old_size)
+    }
+
+    fn shade(&mut self, binding: &Binding) {
+        AllocBox::shade(self, binding)
+    }
+
+    fn set_fn_env(&mut self, fn_bnd: Binding, env: HashSet<Binding>) {
+        AllocBox::set_fn_env(self, fn_bnd, env)
+    }
+
+    fn recapture(&mut self, old: &Binding, new: &Binding) {
+        AllocBox::recapture(self, old, new)
+    }
+
+    fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>) {
+        AllocBox::register_roots(self, roots)
+    }
+
+    fn collect(&mut self, roots: &HashSet<Binding>) {
+        AllocBox::collect(self, roots)
+    }
+
+    fn collect_garbage(&mut self, roots: &HashSet<Binding>) {
+        AllocBox::collect_garbage(self, roots)
+    }
+
+    fn start_cycle(&mut self, roots: &HashSet<Binding>) {
+        AllocBox::start_cycle(self, roots)
+    }
+
+    fn mark_step(&mut self, budget: usize) -> bool {
+        AllocBox::mark_step(self, budget)
+    }
+
+    fn finish_cycle(&mut self) {
+        AllocBox::finish_cycle(self)
+    }
+
+    fn gc_requested(&self) -> bool {
+        AllocBox::gc_requested(self)
+    }
+
+    fn has_room(&self, objects: usize, bytes: usize) -> bool {
+        AllocBox::has_room(self, objects, bytes)
+    }
+
+    fn sweeps(&self) -> usize {
+        AllocBox::sweeps(self)
+    }
+
+    fn forwarding(&self) -> Option<&HashMap<Binding, Binding>> {
+        Some(AllocBox::forwarding(self))
+    }
+
+    fn configure(&mut self, config: GcConfig) {
+        AllocBox::configure(self, config)
+    }
+
+    fn stats(&self) -> GcStats {
+        AllocBox::stats(self)
+    }
+
+    fn reset_stats(&mut self) {
+        AllocBox::reset_stats(self)
+    }
+
+    fn verify_into(&self, report: &mut VerifyReport) {
+        AllocBox::verify_into(self, report)
+    }
 }
 
 impl AllocBox {
@@ -36,23 +200,151 @@ impl AllocBox {
             black_set: HashMap::new(),
             grey_set: HashMap::new(),
             white_set: HashMap::new(),
+            marking: false,
+            nursery: None,
+            accounting: Accounting::new(),
+            cycle_mark_time: Duration::new(0, 0),
+            fn_envs: HashMap::new(),
+            compact: false,
+            forwarding: HashMap::new(),
+            weak: WeakTable::new(),
+        }
+    }
+
+    pub fn with_config(config: GcConfig) -> AllocBox {
+        let mut alloc_box = AllocBox::new();
+        alloc_box.configure(config);
+        alloc_box
+    }
+
+    /// Apply a configuration to a heap that may already hold allocations.
+    /// Turning the nursery off promotes everything in it to the old generation.
+    pub fn configure(&mut self, config: GcConfig) {
+        match config.nursery {
+            Some((promote_age, major_interval)) => match self.nursery {
+                Some(ref mut nursery) => {
+                    nursery.promote_age = promote_age;
+                    nursery.major_interval = major_interval;
+                },
+                None => self.nursery = Some(Nursery::new(promote_age, major_interval)),
+            },
+            None => {
+                self.promote_nursery();
+                self.nursery = None;
+            },
         }
+        self.compact = config.compact;
+        let len = self.len();
+        self.accounting.configure(&config, len);
+    }
+
+    /// A snapshot of the collector's statistics, with live object counts
+    /// computed from the current contents of the heap.
+    pub fn stats(&self) -> GcStats {
+        self.accounting.stats(self.allocations().into_iter().map(|(_, ptr)| &**ptr))
+    }
+
+    /// Clear all statistics. Peaks restart from the current heap size.
+    pub fn reset_stats(&mut self) {
+        let len = self.len();
+        self.accounting.reset_stats(len);
+    }
+
+    pub fn policy(&self) -> &GcPolicy {
+        self.accounting.policy()
+    }
+
+    pub fn set_policy(&mut self, policy: GcPolicy) {
+        let len = self.len();
+        self.accounting.set_policy(policy, len);
+    }
+
+    /// Whether the heap has grown past the limits set by its `GcPolicy` since
+    /// the last collection. The `ScopeManager` polls this at safe points.
+    pub fn gc_requested(&self) -> bool {
+        self.accounting.gc_requested()
+    }
+
+    /// Whether `objects` more allocations totalling `bytes` would fit within the
+    /// hard limits of the heap's `GcPolicy`. Allocations and updates that
+    /// wouldn't fail with `GcError::OutOfMemory`.
+    pub fn has_room(&self, objects: usize, bytes: usize) -> bool {
+        self.accounting.has_room(self.len(), objects, bytes)
+    }
+
+    /// The estimated size of the heap in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.accounting.bytes()
+    }
+
+    /// Create a generational heap. New allocations live in a nursery that is
+    /// traced by cheap minor collections; an allocation is promoted to the old
+    /// generation once it has survived `promote_age` of them, and every
+    /// `major_interval` minor collections a full collection is run instead.
+    pub fn with_nursery(promote_age: usize, major_interval: usize) -> AllocBox {
+        let mut alloc_box = AllocBox::new();
+        alloc_box.nursery = Some(Nursery::new(promote_age, major_interval));
+        alloc_box
     }
 
     pub fn len(&self) -> usize {
-        self.black_set.len() + self.grey_set.len() + self.white_set.len()
+        self.black_set.len() + self.grey_set.len() + self.white_set.len() + self.young_len()
+    }
+
+    /// The number of allocations currently in the nursery.
+    pub fn young_len(&self) -> usize {
+        self.nursery.as_ref().map_or(0, |n| n.young.len())
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    /// Move an allocation to a new binding. It keeps its color or generation,
+    /// its weak edges, and its closure environment if it is a function. Other
+    /// allocations that point to it are not updated.
     pub fn realloc(&mut self, old: &Binding, new: Binding) -> Result<()> {
-        if let Some(ptr) = self.remove_binding(old) {
-            self.white_set.insert(new, ptr);
-            Ok(())
-        } else {
-            Err(GcError::HeapUpdate)
+        let color = match self.color_of(old) {
+            Some(color) => color,
+            None => return Err(GcError::HeapUpdate),
+        };
+        let (remembered, age) = match self.nursery {
+            Some(ref nursery) => (nursery.remembered.contains(old), nursery.ages.get(old).cloned()),
+            None => (false, None),
+        };
+        let ptr = match self.remove_binding(old) {
+            Some(ptr) => ptr,
+            None => return Err(GcError::HeapUpdate),
+        };
+        match color {
+            Color::White => { self.white_set.insert(new.clone(), ptr); },
+            Color::Grey => { self.grey_set.insert(new.clone(), ptr); },
+            Color::Black => { self.black_set.insert(new.clone(), ptr); },
+            Color::Young => if let Some(ref mut nursery) = self.nursery {
+                nursery.insert(new.clone(), ptr);
+            },
+        }
+        if let Some(ref mut nursery) = self.nursery {
+            if remembered { nursery.remembered.insert(new.clone()); }
+            if let Some(age) = age { nursery.ages.insert(new.clone(), age); }
+        }
+        self.weak.rename(old, &new);
+        if let Some(env) = self.fn_envs.remove(old) {
+            self.fn_envs.insert(new, env);
+        }
+        Ok(())
+    }
+
+    /// Free an allocation immediately, without waiting for a collection. Only
+    /// safe when nothing else refers to it. Returns whether anything was freed.
+    pub fn free(&mut self, binding: &Binding) -> bool {
+        match self.remove_binding(binding) {
+            Some(ptr) => {
+                self.accounting.freed(size_of_ptr(&*ptr.borrow()));
+                self.fn_envs.remove(binding);
+                true
+            }
+            None => false,
         }
     }
 
@@ -60,14 +352,14 @@ impl AllocBox {
         for mark in marks {
             if let Some(ptr) = self.white_set.remove(mark) {
                 // Get all child references
-                let child_ids = AllocBox::get_ptr_children(&ptr);
+                let child_ids = self.children_of(mark, &ptr);
                 // Mark current ref as black
                 self.black_set.insert(mark.clone(), ptr);
                 // Mark child references as grey
                 self.grey_children(child_ids);
             } else if let Some(ptr) = self.grey_set.remove(&mark) {
                 // Get all child references
-                let child_ids = AllocBox::get_ptr_children(&ptr);
+                let child_ids = self.children_of(mark, &ptr);
                 // Mark current ref as black
                 self.black_set.insert(mark.clone(), ptr);
                 // Mark child references as grey
@@ -79,8 +371,8 @@ impl AllocBox {
     pub fn mark_ptrs(&mut self) {
         // Mark any grey object as black, and mark all white objs it refs as grey
         let mut new_grey_set = HashMap::new();
-        for (bnd, var) in self.grey_set.drain() {
-            let child_ids = AllocBox::get_ptr_children(&var);
+        for (bnd, var) in mem::replace(&mut self.grey_set, HashMap::new()) {
+            let child_ids = self.children_of(&bnd, &var);
             self.black_set.insert(bnd, var);
             for child_id in child_ids {
                 if let Some(var) = self.white_set.remove(&child_id) {
@@ -91,30 +383,494 @@ impl AllocBox {
         self.grey_set = new_grey_set;
     }
 
+    /// Mark to a fixpoint: keep draining the grey set until every object
+    /// transitively reachable from a black object is black as well.
+    pub fn mark_all(&mut self) {
+        loop {
+            while !self.grey_set.is_empty() {
+                self.mark_ptrs();
+            }
+            if !self.grey_ephemerons() { break; }
+        }
+    }
+
+    /// Register a root set that every collection of this heap starts from, on
+    /// top of the roots it is given. Each manager sharing the heap registers
+    /// the roots of its scopes, so that a collection run by one manager can't
+    /// sweep objects that are only live in another. A set is unregistered once
+    /// it is dropped.
+    pub fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>) {
+        self.accounting.register_roots(roots);
+    }
+
+    /// Run a full stop-the-world collection from the given root set, and the
+    /// registered ones.
+    pub fn collect(&mut self, roots: &HashSet<Binding>) {
+        let start = Instant::now();
+        let roots = self.accounting.all_roots(roots);
+        self.promote_nursery();
+        self.mark_roots(&roots);
+        self.mark_all();
+        let mark_time = start.elapsed();
+        self.finish_sweep(CollectionKind::Full, mark_time);
+    }
+
+    /// Begin an incremental collection cycle by blackening the given roots,
+    /// and the registered ones. Marking then proceeds in bounded increments
+    /// via `mark_step`.
+    pub fn start_cycle(&mut self, roots: &HashSet<Binding>) {
+        let start = Instant::now();
+        let roots = self.accounting.all_roots(roots);
+        self.promote_nursery();
+        self.marking = true;
+        self.mark_roots(&roots);
+        self.cycle_mark_time = start.elapsed();
+    }
+
+    /// Blacken at most `budget` grey objects, greying their white children.
+    /// Returns true once the grey set is empty and the cycle is ready to sweep.
+    pub fn mark_step(&mut self, budget: usize) -> bool {
+        let start = Instant::now();
+        for _ in 0..budget {
+            let bnd = match self.grey_set.keys().next() {
+                Some(bnd) => bnd.clone(),
+                None => break,
+            };
+            if let Some(ptr) = self.grey_set.remove(&bnd) {
+                let child_ids = self.children_of(&bnd, &ptr);
+                self.black_set.insert(bnd, ptr);
+                self.grey_children(child_ids);
+            }
+        }
+        if self.grey_set.is_empty() {
+            self.grey_ephemerons();
+        }
+        self.cycle_mark_time = self.cycle_mark_time + start.elapsed();
+        self.grey_set.is_empty()
+    }
+
+    /// Finish any outstanding marking work and sweep the current cycle.
+    pub fn finish_cycle(&mut self) {
+        let start = Instant::now();
+        self.mark_all();
+        let mark_time = self.cycle_mark_time + start.elapsed();
+        self.finish_sweep(CollectionKind::Incremental, mark_time);
+    }
+
+    pub fn is_marking(&self) -> bool {
+        self.marking
+    }
+
     pub fn sweep_ptrs(&mut self) {
         // Delete all white pointers and reset the GC state.
+        let freed = self.white_set.values().fold(0, |acc, ptr| acc + size_of_ptr(&*ptr.borrow()));
+        self.accounting.freed(freed);
         self.white_set = self.black_set.drain().collect();
         self.grey_set.clear();
         self.black_set.clear();
+        self.marking = false;
+        self.drop_dead_envs();
+        self.clear_dead_weak();
+        let len = self.len();
+        self.accounting.reset_triggers(len);
+    }
+
+    /// Grey a white object so that the current cycle will trace it. Used when
+    /// a binding becomes a root while an incremental cycle is in progress.
+    pub fn shade(&mut self, binding: &Binding) {
+        if !self.marking { return; }
+        if let Some(ptr) = self.white_set.remove(binding) {
+            self.grey_set.insert(binding.clone(), ptr);
+        }
+    }
+
+    /// Collect only the nursery. Young allocations reachable from `roots`, from
+    /// a registered root set, or from an old allocation in the remembered set,
+    /// survive and age by one; everything else in the nursery is freed. The
+    /// old generation is not scanned. Does nothing on a non-generational heap
+    /// or mid-cycle.
+    pub fn minor_collect(&mut self, roots: &HashSet<Binding>) {
+        if self.marking { return; }
+        let start = Instant::now();
+        let mut nursery = match self.nursery.take() {
+            Some(nursery) => nursery,
+            None => return,
+        };
+        // Young roots, plus every young object an old object points to
+        let roots = self.accounting.all_roots(roots);
+        let mut worklist: Vec<Binding> = roots.iter()
+                                              .filter(|bnd| nursery.is_young(bnd))
+                                              .cloned()
+                                              .collect();
+        for bnd in &nursery.remembered {
+            if let Some(ptr) = self.find_id(bnd) {
+                worklist.extend(self.children_of(bnd, ptr));
+            }
+        }
+        let mut live = HashSet::new();
+        loop {
+            while let Some(bnd) = worklist.pop() {
+                if live.contains(&bnd) { continue; }
+                if let Some(ptr) = nursery.young.get(&bnd) {
+                    worklist.extend(self.children_of(&bnd, ptr));
+                } else { continue; }
+                live.insert(bnd);
+            }
+            // A young WeakMap value is live if its map and key are, where every
+            // old object counts as live.
+            worklist = self.weak.live_values(|bnd| live.contains(bnd) || self.find_id(bnd).is_some())
+                                .into_iter()
+                                .filter(|bnd| nursery.is_young(bnd) && !live.contains(bnd))
+                                .collect();
+            if worklist.is_empty() { break; }
+        }
+        let mark_time = start.elapsed();
+
+        let start = Instant::now();
+        let swept = nursery.young.len() - live.len();
+        let mut promoted = Vec::new();
+        for (bnd, ptr) in mem::replace(&mut nursery.young, HashMap::new()) {
+            let age = match nursery.ages.remove(&bnd) {
+                Some(age) => age + 1,
+                None => 1,
+            };
+            if !live.contains(&bnd) {
+                // Dead young object; dropping it frees it.
+                self.accounting.freed(size_of_ptr(&*ptr.borrow()));
+                continue;
+            } else if age >= nursery.promote_age {
+                promoted.push((bnd, ptr));
+            } else {
+                nursery.ages.insert(bnd.clone(), age);
+                nursery.young.insert(bnd, ptr);
+            }
+        }
+        for (bnd, ptr) in promoted {
+            self.white_set.insert(bnd.clone(), ptr);
+            // A promoted object may still point at younger objects
+            nursery.remembered.insert(bnd);
+        }
+        // Forget old objects that no longer point into the nursery
+        let remembered = mem::replace(&mut nursery.remembered, HashSet::new());
+        for bnd in remembered {
+            let points_young = match self.find_id(&bnd) {
+                Some(ptr) => self.children_of(&bnd, ptr).iter().any(|c| nursery.is_young(c)),
+                None => false,
+            };
+            if points_young {
+                nursery.remembered.insert(bnd);
+            }
+        }
+        nursery.minors_since_major += 1;
+        self.nursery = Some(nursery);
+        self.drop_dead_envs();
+        self.clear_dead_weak();
+        let len = self.len();
+        self.accounting.reset_triggers(len);
+        self.accounting.record_cycle(len, CycleStats {
+            kind: CollectionKind::Minor,
+            marked: live.len(),
+            swept: swept,
+            mark_time: mark_time,
+            sweep_time: start.elapsed(),
+        });
+    }
+
+    /// Collect whichever generation is due: a minor collection on a
+    /// generational heap, or a full collection if the heap is not generational
+    /// or enough minor collections have run since the last full one.
+    pub fn collect_garbage(&mut self, roots: &HashSet<Binding>) {
+        let major_due = match self.nursery {
+            Some(ref nursery) => nursery.minors_since_major >= nursery.major_interval,
+            None => true,
+        };
+        if major_due {
+            self.collect(roots);
+        } else {
+            self.minor_collect(roots);
+        }
+    }
+
+    /// Every allocation in the heap, regardless of color or generation.
+    pub fn allocations(&self) -> Vec<(&Binding, &Alloc<JsPtrEnum>)> {
+        let mut allocs: Vec<_> = self.white_set.iter()
+                                               .chain(self.grey_set.iter())
+                                               .chain(self.black_set.iter())
+                                               .collect();
+        if let Some(ref nursery) = self.nursery {
+            allocs.extend(nursery.young.iter());
+        }
+        allocs
+    }
+
+    /// The number of collections run since the stats were last reset.
+    pub fn collections(&self) -> usize {
+        self.accounting.collections()
+    }
+
+    /// The number of collections of any kind this heap has run. Unlike
+    /// `collections`, it isn't reset by `reset_stats`, so it tells whether a
+    /// collection has run since some earlier point.
+    pub fn sweeps(&self) -> usize {
+        self.accounting.sweeps()
+    }
+
+    /// Check that no binding is in more than one color set or generation, and
+    /// that no black object points to a white one.
+    pub fn verify_into(&self, report: &mut VerifyReport) {
+        let mut seen = HashSet::new();
+        for (bnd, _) in self.allocations() {
+            if !seen.insert(bnd) {
+                report.push(Violation::MultipleColors(bnd.clone()));
+            }
+        }
+        // Mid-cycle, a black object may point to a white one until the write
+        // barrier has seen the mutation, e.g. while a `GcRefMut` is held
+        if self.marking { return; }
+        for (bnd, ptr) in &self.black_set {
+            for child in self.children_of(bnd, ptr) {
+                if self.white_set.contains_key(&child) {
+                    report.push(Violation::BlackToWhite(bnd.clone(), child));
+                }
+            }
+        }
+    }
+
+    pub fn color_of(&self, bnd: &Binding) -> Option<Color> {
+        if self.white_set.contains_key(bnd) {
+            Some(Color::White)
+        } else if self.grey_set.contains_key(bnd) {
+            Some(Color::Grey)
+        } else if self.black_set.contains_key(bnd) {
+            Some(Color::Black)
+        } else if self.nursery.as_ref().map_or(false, |n| n.is_young(bnd)) {
+            Some(Color::Young)
+        } else { None }
+    }
+
+    /// Record the heap bindings that a function's closure environment
+    /// captures. They are traced as children of the function, so they live
+    /// exactly as long as it does.
+    pub fn set_fn_env(&mut self, fn_bnd: Binding, env: HashSet<Binding>) {
+        self.fn_envs.insert(fn_bnd.clone(), env);
+        self.write_barrier(&fn_bnd);
+    }
+
+    pub fn fn_env(&self, fn_bnd: &Binding) -> Option<&HashSet<Binding>> {
+        self.fn_envs.get(fn_bnd)
+    }
+
+    /// The bindings the last compacting collection moved allocations from,
+    /// mapped to the bindings it moved them to. Empty if the last full or
+    /// incremental collection didn't compact; minor collections never move
+    /// anything and leave it as it was.
+    pub fn forwarding(&self) -> &HashMap<Binding, Binding> {
+        &self.forwarding
+    }
+
+    /// Make every closure environment that captures `old` capture `new`
+    /// instead, as when a captured variable moves to a new binding.
+    pub fn recapture(&mut self, old: &Binding, new: &Binding) {
+        let mut changed = Vec::new();
+        for (fn_bnd, env) in &mut self.fn_envs {
+            if env.remove(old) {
+                env.insert(new.clone());
+                changed.push(fn_bnd.clone());
+            }
+        }
+        for fn_bnd in changed {
+            self.write_barrier(&fn_bnd);
+        }
+    }
+
+    /// Make `owner` refer weakly to `target`, as a `WeakRef` or `WeakSet`
+    /// does. Weak references are not traced, so they don't keep their target
+    /// alive: once it is freed, the reference is cleared and reported by
+    /// `take_cleared_weak`. A target that is also a property of its owner is
+    /// traced like any other property.
+    pub fn add_weak_ref(&mut self, owner: Binding, target: Binding) {
+        self.weak.refs.entry(owner).or_insert_with(HashSet::new).insert(target);
+    }
+
+    /// Remove a weak reference, returning whether it existed.
+    pub fn remove_weak_ref(&mut self, owner: &Binding, target: &Binding) -> bool {
+        self.weak.refs.get_mut(owner).map_or(false, |targets| targets.remove(target))
+    }
+
+    /// The targets `owner` still refers to weakly.
+    pub fn weak_refs(&self, owner: &Binding) -> Option<&HashSet<Binding>> {
+        self.weak.refs.get(owner)
+    }
+
+    /// Add an entry to the `WeakMap` `owner`. The entry keeps `value` alive
+    /// only while both the map and `key` are alive; once `key` is freed, the
+    /// entry is cleared and reported by `take_cleared_weak`.
+    pub fn set_ephemeron(&mut self, owner: Binding, key: Binding, value: Binding) {
+        // The map and key may already be black, in which case nothing would
+        // trace the value before the cycle ends.
+        self.shade(&value);
+        self.weak.ephemerons.entry(owner).or_insert_with(HashMap::new).insert(key, value);
+    }
+
+    /// Remove an entry from the `WeakMap` `owner`, returning its value.
+    pub fn remove_ephemeron(&mut self, owner: &Binding, key: &Binding) -> Option<Binding> {
+        self.weak.ephemerons.get_mut(owner).and_then(|entries| entries.remove(key))
+    }
+
+    /// The value of the entry for `key` in the `WeakMap` `owner`.
+    pub fn ephemeron(&self, owner: &Binding, key: &Binding) -> Option<&Binding> {
+        self.weak.ephemerons.get(owner).and_then(|entries| entries.get(key))
+    }
+
+    /// Every weak reference and `WeakMap` entry cleared since the last call, as
+    /// `(owner, target)` pairs, where the target is the freed referent or key.
+    pub fn take_cleared_weak(&mut self) -> Vec<(Binding, Binding)> {
+        mem::replace(&mut self.weak.cleared, Vec::new())
     }
 
     pub fn find_id(&self, bnd: &Binding) -> Option<&Alloc<JsPtrEnum>> {
         self.white_set.get(bnd).or(
             self.grey_set.get(bnd).or(
-                self.black_set.get(bnd)))
+                self.black_set.get(bnd).or(
+                    self.nursery.as_ref().and_then(|n| n.young.get(bnd)))))
     }
 
     pub fn update_ptr(&mut self, binding: &Binding, ptr: JsPtrEnum) -> Result<()> {
-        if let Entry::Occupied(mut view) = self.find_id_mut(binding) {
-            let inner = view.get_mut();
-            *inner.borrow_mut() = ptr;
-            Ok(())
+        let alloc = match self.find_id(binding) {
+            Some(alloc) => alloc.clone(),
+            None => return Err(GcError::HeapUpdate),
+        };
+        let old_size = size_of_ptr(&*alloc.borrow());
+        let new_size = size_of_ptr(&ptr);
+        if !self.has_room(0, new_size.saturating_sub(old_size)) {
+            return Err(GcError::OutOfMemory);
+        }
+        *alloc.borrow_mut() = ptr;
+        let len = self.len();
+        self.accounting.resized(len, old_size, new_size);
+        self.write_barrier(binding);
+        Ok(())
+    }
+
+    /// Account for an allocation that was mutated in place through a
+    /// `GcRefMut`, given its size before the mutation. A mutation can't be
+    /// refused after the fact, so one that grows the heap past the hard limits
+    /// of its `GcPolicy` is kept, but reported as `GcError::OutOfMemory`.
+    /// Further allocations fail until a collection makes room.
+    pub fn mutated(&mut self, binding: &Binding, old_size: usize) -> Result<()> {
+        let new_size = match self.find_id(binding) {
+            Some(alloc) => size_of_ptr(&*alloc.borrow()),
+            None => return Ok(()),
+        };
+        let len = self.len();
+        self.accounting.resized(len, old_size, new_size);
+        self.write_barrier(binding);
+        if self.has_room(0, 0) { Ok(()) } else { Err(GcError::OutOfMemory) }
+    }
+
+    /// Write barrier, run whenever an allocation's contents change.
+    /// Generational: an old object that now points into the nursery is added
+    /// to the remembered set, so minor collections treat its young children as
+    /// live. Tri-color: if a black object gained a pointer to a white object
+    /// mid-cycle, move it back to grey so it gets rescanned before the sweep;
+    /// otherwise the white object could be freed while still reachable.
+    fn write_barrier(&mut self, binding: &Binding) {
+        let child_ids = match self.find_id(binding) {
+            Some(ptr) => self.children_of(binding, ptr),
+            None => return,
+        };
+        if let Some(ref mut nursery) = self.nursery {
+            if !nursery.is_young(binding) && child_ids.iter().any(|c| nursery.is_young(c)) {
+                nursery.remembered.insert(binding.clone());
+            }
+        }
+        if !self.marking { return; }
+        let regrey = self.black_set.contains_key(binding) &&
+                     child_ids.iter().any(|child| self.white_set.contains_key(child));
+        if regrey {
+            if let Some(ptr) = self.black_set.remove(binding) {
+                self.grey_set.insert(binding.clone(), ptr);
+            }
+        }
+    }
+
+    // New allocations start out young on a generational heap. Allocations made
+    // during an incremental cycle start out grey instead, so the sweep that ends
+    // the cycle cannot free them before they were ever traced.
+    fn insert_fresh(&mut self, binding: Binding, ptr: Alloc<JsPtrEnum>) {
+        if self.marking {
+            self.grey_set.insert(binding, ptr);
+        } else if let Some(ref mut nursery) = self.nursery {
+            nursery.insert(binding, ptr);
         } else {
-            Err(GcError::HeapUpdate)
+            self.white_set.insert(binding, ptr);
         }
     }
 
+    // Sweep a cycle whose marking is complete, and record its metrics.
+    fn finish_sweep(&mut self, kind: CollectionKind, mark_time: Duration) {
+        let marked = self.black_set.len();
+        let swept = self.white_set.len();
+        let start = Instant::now();
+        self.sweep_ptrs();
+        self.forwarding = if self.compact { self.compact() } else { HashMap::new() };
+        let len = self.len();
+        self.accounting.record_cycle(len, CycleStats {
+            kind: kind,
+            marked: marked,
+            swept: swept,
+            mark_time: mark_time,
+            sweep_time: start.elapsed(),
+        });
+    }
+
+    // Move every survivor of a sweep to a new binding, and its value out of
+    // the storage it was allocated in into a fresh allocation, one after
+    // another, rather than leaving it among the holes the dead left behind.
+    // Where the new storage lands is up to the system allocator. References
+    // between allocations, the weak edges and the registered root sets are
+    // pointed at the new bindings. Returns the forwarding table.
+    fn compact(&mut self) -> HashMap<Binding, Binding> {
+        let live: Vec<Binding> = self.allocations().into_iter().map(|(bnd, _)| bnd.clone()).collect();
+        let mut forwarding = HashMap::new();
+        for old in live {
+            let new = Binding::anon();
+            if self.realloc(&old, new.clone()).is_ok() {
+                forwarding.insert(old, new);
+            }
+        }
+        relocate_all(&mut self.white_set, &forwarding);
+        relocate_all(&mut self.grey_set, &forwarding);
+        relocate_all(&mut self.black_set, &forwarding);
+        if let Some(ref mut nursery) = self.nursery {
+            relocate_all(&mut nursery.young, &forwarding);
+        }
+        for env in self.fn_envs.values_mut() {
+            let moved: HashSet<Binding> = env.iter().map(|bnd| forwarded(&forwarding, bnd)).collect();
+            *env = moved;
+        }
+        self.weak.remap(&forwarding);
+        self.accounting.remap_roots(&forwarding);
+        forwarding
+    }
+
+    // Move the whole nursery into the old generation, so that a full
+    // collection can trace through young objects as well as old ones.
+    fn promote_nursery(&mut self) {
+        let young = match self.nursery {
+            Some(ref mut nursery) => nursery.evacuate(),
+            None => return,
+        };
+        self.white_set.extend(young);
+    }
+
     fn remove_binding(&mut self, binding: &Binding) -> Option<Alloc<JsPtrEnum>> {
+        if let Some(ref mut nursery) = self.nursery {
+            nursery.remembered.remove(binding);
+            if let Some(ptr) = nursery.remove(binding) {
+                return Some(ptr);
+            }
+        }
         self.white_set.remove(binding).or(
             self.grey_set.remove(binding).or(
                 self.black_set.remove(binding)))
@@ -128,32 +884,105 @@ impl AllocBox {
         }
     }
 
-    fn get_ptr_children(ptr: &Alloc<JsPtrEnum>) -> HashSet<Binding> {
-        if let JsPtrEnum::JsObj(ref obj) = *ptr.borrow() {
-            obj.get_children()
-        } else { HashSet::new() }
+    // The bindings an allocation points to: an object's properties, or the
+    // environment a function closes over.
+    fn children_of(&self, bnd: &Binding, ptr: &Alloc<JsPtrEnum>) -> HashSet<Binding> {
+        AllocBox::get_ptr_children(ptr, self.fn_envs.get(bnd))
     }
 
-    fn find_id_mut(&mut self, bnd: &Binding) -> Entry<Binding, Alloc<JsPtrEnum>> {
-        if let e @ Entry::Occupied(_) = self.white_set.entry(bnd.clone()) {
-            e
-        } else if let e @ Entry::Occupied(_) = self.grey_set.entry(bnd.clone()) {
-            e
-        } else { self.black_set.entry(bnd.clone()) }
+    // The bindings a value points to, given the closure environment recorded
+    // for it, if it is a function. Functions don't hold their captured
+    // bindings themselves, so the heap keeps them in `fn_envs`.
+    fn get_ptr_children(ptr: &RefCell<JsPtrEnum>, env: Option<&HashSet<Binding>>) -> HashSet<Binding> {
+        match *ptr.borrow() {
+            JsPtrEnum::JsObj(ref obj) => obj.get_children(),
+            JsPtrEnum::JsFn(_) => env.cloned().unwrap_or_else(HashSet::new),
+            _ => HashSet::new(),
+        }
+    }
+
+    // Grey the white values of WeakMap entries whose map and key are both
+    // black. Returns whether any were greyed.
+    fn grey_ephemerons(&mut self) -> bool {
+        let values = self.weak.live_values(|bnd| self.black_set.contains_key(bnd));
+        let mut greyed = false;
+        for value in values {
+            if let Some(ptr) = self.white_set.remove(&value) {
+                self.grey_set.insert(value, ptr);
+                greyed = true;
+            }
+        }
+        greyed
+    }
+
+    // Clear the weak references and WeakMap entries whose target or key has
+    // been freed.
+    fn clear_dead_weak(&mut self) {
+        let mut weak = mem::replace(&mut self.weak, WeakTable::new());
+        weak.clear_dead(|bnd| self.find_id(bnd).is_some());
+        self.weak = weak;
+    }
+
+    // Forget the environments of functions that have been freed.
+    fn drop_dead_envs(&mut self) {
+        let dead: Vec<Binding> = self.fn_envs.keys()
+                                             .filter(|bnd| self.find_id(bnd).is_none())
+                                             .cloned()
+                                             .collect();
+        for bnd in dead {
+            self.fn_envs.remove(&bnd);
+        }
     }
 }
 
+/// The binding an allocation at `bnd` was moved to, or `bnd` itself if it
+/// didn't move.
+pub fn forwarded(forwarding: &HashMap<Binding, Binding>, bnd: &Binding) -> Binding {
+    forwarding.get(bnd).unwrap_or(bnd).clone()
+}
+
+// Move each allocation's value into fresh storage, pointing its children at
+// the bindings they were moved to. The value is only copied if something
+// else still holds the old storage.
+fn relocate_all(allocs: &mut HashMap<Binding, Alloc<JsPtrEnum>>,
+                forwarding: &HashMap<Binding, Binding>) {
+    *allocs = mem::replace(allocs, HashMap::new())
+        .into_iter()
+        .map(|(bnd, alloc)| {
+            let mut ptr = match Rc::try_unwrap(alloc) {
+                Ok(cell) => cell.into_inner(),
+                Err(alloc) => alloc.borrow().clone(),
+            };
+            if let JsPtrEnum::JsObj(ref mut obj) = ptr {
+                for var in obj.dict.values_mut() {
+                    var.binding = forwarded(forwarding, &var.binding);
+                }
+            }
+            (bnd, Rc::new(RefCell::new(ptr)))
+        })
+        .collect();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::stats::CollectionKind;
+    use std::cell::RefCell;
     use std::collections::hash_set::HashSet;
+    use std::rc::Rc;
 
     use gc_error::GcError;
     use js_types::allocator::Allocator;
     use js_types::binding::Binding;
-    use js_types::js_var::JsPtrEnum;
+    use js_types::js_obj::JsObjStruct;
+    use js_types::js_var::{JsKey, JsPtrEnum, JsPtrTag, JsType, JsVar};
     use js_types::js_str::JsStrStruct;
     use test_utils;
+    use verify::{Violation, VerifyReport};
+
+    fn key(s: &str) -> JsKey {
+        JsKey::JsSym(s.to_string())
+    }
 
     #[test]
     fn test_len() {
@@ -231,4 +1060,894 @@ mod tests {
         assert!(ab.black_set.contains_key(&x_bnd));
         assert!(ab.black_set.contains_key(&y_bnd));
     }
+
+    #[test]
+    fn test_mark_ptrs_single_pass() {
+        let heap = test_utils::make_alloc_box();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("deep");
+        let (c, c_ptr, _) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        let (b, b_ptr, _) = test_utils::make_obj(vec![(key("c"), c, Some(c_ptr))], heap.clone());
+        heap.borrow_mut().alloc(b.binding.clone(), b_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(b.binding);
+        heap.borrow_mut().mark_roots(&roots);
+        heap.borrow_mut().mark_ptrs();
+        // A single pass only reaches one hop past the roots
+        assert!(!heap.borrow().grey_set.is_empty());
+        assert!(heap.borrow().white_set.contains_key(&s_bnd) ||
+                heap.borrow().grey_set.contains_key(&s_bnd));
+    }
+
+    #[test]
+    fn test_collect_deep_graph() {
+        let heap = test_utils::make_alloc_box();
+        // a -> b -> c -> "deep"
+        let (s, s_ptr, s_bnd) = test_utils::make_str("deep");
+        let (c, c_ptr, c_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        let (b, b_ptr, b_bnd) = test_utils::make_obj(vec![(key("c"), c, Some(c_ptr))], heap.clone());
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("b"), b, Some(b_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        assert_eq!(heap.borrow().len(), 4);
+
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().collect(&roots);
+        assert_eq!(heap.borrow().len(), 4);
+        for bnd in &[a_bnd, b_bnd, c_bnd, s_bnd] {
+            assert!(heap.borrow().find_id(bnd).is_some());
+        }
+        assert!(heap.borrow().grey_set.is_empty());
+        assert!(heap.borrow().black_set.is_empty());
+    }
+
+    #[test]
+    fn test_collect_unreachable_deep_graph() {
+        let heap = test_utils::make_alloc_box();
+        let (s, s_ptr, _) = test_utils::make_str("deep");
+        let (c, c_ptr, _) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        let (b, b_ptr, _) = test_utils::make_obj(vec![(key("c"), c, Some(c_ptr))], heap.clone());
+        heap.borrow_mut().alloc(b.binding, b_ptr).unwrap();
+        assert_eq!(heap.borrow().len(), 3);
+
+        heap.borrow_mut().collect(&HashSet::new());
+        assert!(heap.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_collect_cyclic_graph() {
+        let heap = test_utils::make_alloc_box();
+        // a -> b -> a, with a string hanging off of b
+        let a = JsVar::new(JsType::JsPtr(JsPtrTag::JsObj));
+        let a_bnd = a.binding.clone();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("cycle");
+        let (b, b_ptr, b_bnd) = test_utils::make_obj(vec![(key("a"), a, None),
+                                                          (key("s"), s, Some(s_ptr))],
+                                                     heap.clone());
+        let a_ptr = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("b"), b, Some(b_ptr))],
+                                                      &mut *heap.borrow_mut()));
+        heap.borrow_mut().alloc(a_bnd.clone(), a_ptr).unwrap();
+        assert_eq!(heap.borrow().len(), 3);
+
+        let mut roots = HashSet::new();
+        roots.insert(b_bnd.clone());
+        heap.borrow_mut().collect(&roots);
+        assert_eq!(heap.borrow().len(), 3);
+        for bnd in &[a_bnd, b_bnd, s_bnd] {
+            assert!(heap.borrow().find_id(bnd).is_some());
+        }
+
+        // Once the cycle is unrooted, the whole thing goes away
+        heap.borrow_mut().collect(&HashSet::new());
+        assert!(heap.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_mark_step_budget() {
+        let heap = test_utils::make_alloc_box();
+        // a -> b -> c -> "deep"
+        let (s, s_ptr, s_bnd) = test_utils::make_str("deep");
+        let (c, c_ptr, _) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        let (b, b_ptr, _) = test_utils::make_obj(vec![(key("c"), c, Some(c_ptr))], heap.clone());
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("b"), b, Some(b_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd);
+        heap.borrow_mut().start_cycle(&roots);
+        assert!(heap.borrow().is_marking());
+        // b, then c, then the string: one object per unit of work
+        assert!(!heap.borrow_mut().mark_step(1));
+        assert!(!heap.borrow_mut().mark_step(1));
+        assert!(heap.borrow().grey_set.contains_key(&s_bnd));
+        assert!(heap.borrow_mut().mark_step(1));
+        assert_eq!(heap.borrow().black_set.len(), 4);
+
+        heap.borrow_mut().finish_cycle();
+        assert!(!heap.borrow().is_marking());
+        assert_eq!(heap.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_alloc_during_cycle() {
+        let mut ab = AllocBox::new();
+        ab.start_cycle(&HashSet::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert!(ab.grey_set.contains_key(&x_bnd));
+        ab.finish_cycle();
+        assert!(ab.find_id(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_write_barrier() {
+        let heap = test_utils::make_alloc_box();
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![], heap.clone());
+        let (s, s_ptr, s_bnd) = test_utils::make_str("late");
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        heap.borrow_mut().alloc(s.binding.clone(), s_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().start_cycle(&roots);
+        assert!(heap.borrow_mut().mark_step(10));
+        assert!(heap.borrow().black_set.contains_key(&a_bnd));
+
+        // The black root now gains a pointer to the still-white string
+        let new_a = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("s"), s, None)],
+                                                      &mut *heap.borrow_mut()));
+        heap.borrow_mut().update_ptr(&a_bnd, new_a).unwrap();
+        assert!(heap.borrow().grey_set.contains_key(&a_bnd));
+
+        heap.borrow_mut().finish_cycle();
+        assert!(heap.borrow().find_id(&a_bnd).is_some());
+        assert!(heap.borrow().find_id(&s_bnd).is_some());
+    }
+
+    #[test]
+    fn test_shade() {
+        let mut ab = AllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        ab.shade(&x_bnd);
+        // Shading is a no-op outside of a cycle
+        assert!(ab.white_set.contains_key(&x_bnd));
+        ab.start_cycle(&HashSet::new());
+        ab.shade(&x_bnd);
+        assert!(ab.grey_set.contains_key(&x_bnd));
+        ab.finish_cycle();
+        assert!(ab.find_id(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_nursery_alloc() {
+        let mut ab = AllocBox::with_nursery(2, 4);
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert_eq!(ab.len(), 1);
+        assert_eq!(ab.young_len(), 1);
+        assert!(ab.white_set.is_empty());
+        assert!(ab.find_id(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_minor_collect() {
+        let mut ab = AllocBox::with_nursery(2, 4);
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        ab.alloc(y_bnd.clone(), y_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        ab.minor_collect(&roots);
+        // The unrooted string died young; the rooted one survived but is not
+        // old enough to be promoted yet.
+        assert!(ab.find_id(&y_bnd).is_none());
+        assert_eq!(ab.young_len(), 1);
+
+        ab.minor_collect(&roots);
+        assert_eq!(ab.young_len(), 0);
+        assert!(ab.white_set.contains_key(&x_bnd));
+    }
+
+    #[test]
+    fn test_minor_collect_traces_young_children() {
+        let heap = Rc::new(RefCell::new(AllocBox::with_nursery(1, 4)));
+        let (s, s_ptr, s_bnd) = test_utils::make_str("child");
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        assert_eq!(heap.borrow().young_len(), 2);
+
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().minor_collect(&roots);
+        assert_eq!(heap.borrow().young_len(), 0);
+        assert!(heap.borrow().white_set.contains_key(&a_bnd));
+        assert!(heap.borrow().white_set.contains_key(&s_bnd));
+    }
+
+    #[test]
+    fn test_minor_collect_remembered_set() {
+        let heap = Rc::new(RefCell::new(AllocBox::with_nursery(1, 4)));
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        // Promote the object into the old generation
+        heap.borrow_mut().minor_collect(&roots);
+        assert!(heap.borrow().white_set.contains_key(&a_bnd));
+
+        // Point the old object at a brand new young string
+        let (s, s_ptr, s_bnd) = test_utils::make_str("young");
+        let new_a = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("s"), s, Some(s_ptr))],
+                                                      &mut *heap.borrow_mut()));
+        heap.borrow_mut().update_ptr(&a_bnd, new_a).unwrap();
+        assert_eq!(heap.borrow().young_len(), 1);
+
+        // Nothing young is rooted, but the old object remembers its young child
+        heap.borrow_mut().minor_collect(&HashSet::new());
+        assert!(heap.borrow().find_id(&s_bnd).is_some());
+    }
+
+    #[test]
+    fn test_collect_garbage_major_interval() {
+        let mut ab = AllocBox::with_nursery(1, 1);
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        // Minor: promotes x
+        ab.collect_garbage(&roots);
+        assert!(ab.white_set.contains_key(&x_bnd));
+        // Old objects are never freed by minor collections...
+        ab.minor_collect(&HashSet::new());
+        assert!(ab.find_id(&x_bnd).is_some());
+        // ...but a major collection is now due, and scans the old generation
+        ab.collect_garbage(&HashSet::new());
+        assert!(ab.is_empty());
+    }
+
+    #[test]
+    fn test_collect_promotes_nursery() {
+        let mut ab = AllocBox::with_nursery(4, 4);
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        ab.alloc(Binding::anon(), y_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        ab.collect(&roots);
+        assert_eq!(ab.young_len(), 0);
+        assert_eq!(ab.len(), 1);
+        assert!(ab.white_set.contains_key(&x_bnd));
+    }
+
+    #[test]
+    fn test_policy_requests_gc() {
+        let mut ab = AllocBox::new();
+        ab.set_policy(GcPolicy {
+            object_threshold: 2,
+            byte_threshold: usize::max_value(),
+            growth_factor: 2.0,
+            min_interval: 1,
+            ..GcPolicy::default()
+        });
+        let mut roots = HashSet::new();
+        for _ in 0..2 {
+            let (x, x_ptr, _) = test_utils::make_str("x");
+            roots.insert(x.binding.clone());
+            ab.alloc(x.binding, x_ptr).unwrap();
+        }
+        assert!(!ab.gc_requested());
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        ab.alloc(Binding::anon(), y_ptr).unwrap();
+        assert!(ab.gc_requested());
+
+        // Two objects survive, so the next limit grows to four
+        ab.collect(&roots);
+        assert!(!ab.gc_requested());
+        assert_eq!(ab.len(), 2);
+        for _ in 0..2 {
+            let (_, z_ptr, _) = test_utils::make_str("z");
+            ab.alloc(Binding::anon(), z_ptr).unwrap();
+        }
+        assert!(!ab.gc_requested());
+        let (_, z_ptr, _) = test_utils::make_str("z");
+        ab.alloc(Binding::anon(), z_ptr).unwrap();
+        assert!(ab.gc_requested());
+    }
+
+    #[test]
+    fn test_policy_min_interval() {
+        let mut ab = AllocBox::new();
+        ab.set_policy(GcPolicy {
+            object_threshold: 0,
+            byte_threshold: 0,
+            growth_factor: 1.0,
+            min_interval: 3,
+            ..GcPolicy::default()
+        });
+        for i in 0..3 {
+            assert!(!ab.gc_requested());
+            let (_, x_ptr, _) = test_utils::make_str("x");
+            ab.alloc(Binding::anon(), x_ptr).unwrap();
+            assert_eq!(ab.gc_requested(), i == 2);
+        }
+    }
+
+    #[test]
+    fn test_byte_len() {
+        let mut ab = AllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let small = ab.byte_len();
+        assert!(small > 0);
+        let (_, y_ptr, _) = test_utils::make_str("a much longer string than x");
+        ab.update_ptr(&x_bnd, y_ptr).unwrap();
+        assert!(ab.byte_len() > small);
+        ab.collect(&HashSet::new());
+        assert_eq!(ab.byte_len(), 0);
+    }
+
+    #[test]
+    fn test_free() {
+        let mut ab = AllocBox::with_nursery(1, 4);
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        ab.minor_collect(&roots);
+        ab.alloc(y_bnd.clone(), y_ptr).unwrap();
+        assert_eq!(ab.young_len(), 1);
+
+        // Both old and young allocations can be freed
+        assert!(ab.free(&x_bnd));
+        assert!(ab.free(&y_bnd));
+        assert!(!ab.free(&x_bnd));
+        assert!(ab.is_empty());
+        assert_eq!(ab.byte_len(), 0);
+    }
+
+    #[test]
+    fn test_realloc() {
+        let mut ab = AllocBox::with_nursery(1, 4);
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        ab.set_fn_env(f_bnd.clone(), env);
+
+        let g_bnd = Binding::anon();
+        assert!(ab.realloc(&f_bnd, g_bnd.clone()).is_ok());
+        assert!(ab.find_id(&f_bnd).is_none());
+        assert_eq!(ab.color_of(&g_bnd), Some(Color::Young));
+        assert!(ab.fn_env(&f_bnd).is_none());
+        assert!(ab.fn_env(&g_bnd).unwrap().contains(&x_bnd));
+        assert!(matches!(ab.realloc(&f_bnd, Binding::anon()), Err(GcError::HeapUpdate)));
+    }
+
+    #[test]
+    fn test_compact() {
+        let heap = Rc::new(RefCell::new(AllocBox::with_config(GcConfig {
+            compact: true,
+            ..GcConfig::default()
+        })));
+        let (s, s_ptr, s_bnd) = test_utils::make_str("moved");
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        let (_, dead_ptr, dead_bnd) = test_utils::make_str("dead");
+        heap.borrow_mut().alloc(dead_bnd.clone(), dead_ptr).unwrap();
+        let old_alloc = heap.borrow().find_id(&a_bnd).unwrap().clone();
+
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().collect(&roots);
+        let heap = heap.borrow();
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.forwarding().len(), 2);
+        assert!(!heap.forwarding().contains_key(&dead_bnd));
+        assert!(heap.find_id(&a_bnd).is_none());
+        assert!(heap.find_id(&s_bnd).is_none());
+
+        // The survivor moved to new storage, and its property points at the
+        // moved string
+        let new_a = &heap.forwarding()[&a_bnd];
+        let new_alloc = heap.find_id(new_a).unwrap();
+        let old_raw: *const RefCell<JsPtrEnum> = &*old_alloc;
+        let new_raw: *const RefCell<JsPtrEnum> = &**new_alloc;
+        assert!(old_raw != new_raw);
+        match *new_alloc.borrow() {
+            JsPtrEnum::JsObj(ref obj) =>
+                assert_eq!(obj.dict[&key("s")].binding, heap.forwarding()[&s_bnd]),
+            _ => panic!("expected an object"),
+        };
+    }
+
+    #[test]
+    fn test_compact_disabled() {
+        let mut ab = AllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        ab.collect(&roots);
+        assert!(ab.forwarding().is_empty());
+        assert!(ab.find_id(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_compact_remaps_roots_and_envs() {
+        let mut ab = AllocBox::with_config(GcConfig { compact: true, ..GcConfig::default() });
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        ab.set_fn_env(f_bnd.clone(), env);
+        let registered = Rc::new(RefCell::new(HashSet::new()));
+        registered.borrow_mut().insert(f_bnd.clone());
+        ab.register_roots(&registered);
+
+        ab.collect(&HashSet::new());
+        let (f, x) = (ab.forwarding()[&f_bnd].clone(), ab.forwarding()[&x_bnd].clone());
+        assert!(registered.borrow().contains(&f));
+        assert!(ab.fn_env(&f).unwrap().contains(&x));
+
+        // The remapped roots and environment keep both alive
+        ab.collect(&HashSet::new());
+        assert_eq!(ab.len(), 2);
+    }
+
+    #[test]
+    fn test_recapture() {
+        let mut ab = AllocBox::new();
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        ab.set_fn_env(f_bnd.clone(), env);
+
+        // The closure follows its captured variable to a new binding
+        let y_bnd = Binding::anon();
+        ab.alloc(y_bnd.clone(), x_ptr).unwrap();
+        ab.recapture(&x_bnd, &y_bnd);
+        assert!(!ab.fn_env(&f_bnd).unwrap().contains(&x_bnd));
+        let mut roots = HashSet::new();
+        roots.insert(f_bnd);
+        ab.collect(&roots);
+        assert!(ab.find_id(&y_bnd).is_some());
+    }
+
+    #[test]
+    fn test_weak_ref() {
+        let mut ab = AllocBox::new();
+        let (_, w_ptr, w_bnd) = test_utils::make_str("weakref");
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        ab.alloc(w_bnd.clone(), w_ptr).unwrap();
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        ab.alloc(y_bnd.clone(), y_ptr).unwrap();
+        ab.add_weak_ref(w_bnd.clone(), x_bnd.clone());
+        ab.add_weak_ref(w_bnd.clone(), y_bnd.clone());
+
+        // Weak references don't keep their targets alive
+        let mut roots = HashSet::new();
+        roots.insert(w_bnd.clone());
+        roots.insert(y_bnd.clone());
+        ab.collect(&roots);
+        assert!(ab.find_id(&x_bnd).is_none());
+        assert!(ab.find_id(&y_bnd).is_some());
+        assert_eq!(ab.take_cleared_weak(), vec![(w_bnd.clone(), x_bnd.clone())]);
+        assert!(ab.take_cleared_weak().is_empty());
+        assert!(!ab.weak_refs(&w_bnd).unwrap().contains(&x_bnd));
+
+        assert!(ab.remove_weak_ref(&w_bnd, &y_bnd));
+        assert!(!ab.remove_weak_ref(&w_bnd, &y_bnd));
+        ab.collect(&HashSet::new());
+        assert!(ab.weak_refs(&w_bnd).is_none());
+        assert!(ab.take_cleared_weak().is_empty());
+    }
+
+    #[test]
+    fn test_ephemeron() {
+        let mut ab = AllocBox::new();
+        let (_, m_ptr, m_bnd) = test_utils::make_str("weakmap");
+        let (_, k_ptr, k_bnd) = test_utils::make_str("key");
+        let (_, v_ptr, v_bnd) = test_utils::make_str("value");
+        ab.alloc(m_bnd.clone(), m_ptr).unwrap();
+        ab.alloc(k_bnd.clone(), k_ptr).unwrap();
+        ab.alloc(v_bnd.clone(), v_ptr).unwrap();
+        ab.set_ephemeron(m_bnd.clone(), k_bnd.clone(), v_bnd.clone());
+
+        // The value lives as long as both the map and the key
+        let mut roots = HashSet::new();
+        roots.insert(m_bnd.clone());
+        roots.insert(k_bnd.clone());
+        ab.collect(&roots);
+        assert_eq!(ab.len(), 3);
+        assert_eq!(ab.ephemeron(&m_bnd, &k_bnd), Some(&v_bnd));
+
+        roots.remove(&k_bnd);
+        ab.collect(&roots);
+        assert_eq!(ab.len(), 1);
+        assert!(ab.ephemeron(&m_bnd, &k_bnd).is_none());
+        assert_eq!(ab.take_cleared_weak(), vec![(m_bnd, k_bnd)]);
+    }
+
+    #[test]
+    fn test_ephemeron_chain() {
+        let mut ab = AllocBox::new();
+        let (_, m_ptr, m_bnd) = test_utils::make_str("weakmap");
+        let (_, a_ptr, a_bnd) = test_utils::make_str("a");
+        let (_, b_ptr, b_bnd) = test_utils::make_str("b");
+        let (_, c_ptr, c_bnd) = test_utils::make_str("c");
+        ab.alloc(m_bnd.clone(), m_ptr).unwrap();
+        ab.alloc(a_bnd.clone(), a_ptr).unwrap();
+        ab.alloc(b_bnd.clone(), b_ptr).unwrap();
+        ab.alloc(c_bnd.clone(), c_ptr).unwrap();
+        // a => b => c, where only a is otherwise reachable
+        ab.set_ephemeron(m_bnd.clone(), b_bnd.clone(), c_bnd.clone());
+        ab.set_ephemeron(m_bnd.clone(), a_bnd.clone(), b_bnd.clone());
+
+        let mut roots = HashSet::new();
+        roots.insert(m_bnd.clone());
+        roots.insert(a_bnd.clone());
+        ab.collect(&roots);
+        assert_eq!(ab.len(), 4);
+
+        // Without the map, its entries keep nothing alive
+        roots.remove(&m_bnd);
+        ab.collect(&roots);
+        assert_eq!(ab.len(), 1);
+    }
+
+    #[test]
+    fn test_ephemeron_incremental() {
+        let mut ab = AllocBox::new();
+        let (_, m_ptr, m_bnd) = test_utils::make_str("weakmap");
+        let (_, k_ptr, k_bnd) = test_utils::make_str("key");
+        let (_, v_ptr, v_bnd) = test_utils::make_str("value");
+        ab.alloc(m_bnd.clone(), m_ptr).unwrap();
+        ab.alloc(k_bnd.clone(), k_ptr).unwrap();
+        ab.alloc(v_bnd.clone(), v_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(m_bnd.clone());
+        roots.insert(k_bnd.clone());
+        ab.start_cycle(&roots);
+        // The map and key are already black when the entry is added
+        ab.set_ephemeron(m_bnd.clone(), k_bnd.clone(), v_bnd.clone());
+        while !ab.mark_step(1) {}
+        ab.finish_cycle();
+        assert!(ab.find_id(&v_bnd).is_some());
+    }
+
+    #[test]
+    fn test_ephemeron_minor_collect() {
+        let mut ab = AllocBox::with_nursery(4, 100);
+        let (_, m_ptr, m_bnd) = test_utils::make_str("weakmap");
+        let (_, k_ptr, k_bnd) = test_utils::make_str("key");
+        let (_, v_ptr, v_bnd) = test_utils::make_str("value");
+        ab.alloc(m_bnd.clone(), m_ptr).unwrap();
+        ab.alloc(k_bnd.clone(), k_ptr).unwrap();
+        ab.alloc(v_bnd.clone(), v_ptr).unwrap();
+        ab.set_ephemeron(m_bnd.clone(), k_bnd.clone(), v_bnd.clone());
+
+        let mut roots = HashSet::new();
+        roots.insert(m_bnd.clone());
+        roots.insert(k_bnd.clone());
+        ab.minor_collect(&roots);
+        assert_eq!(ab.young_len(), 3);
+
+        roots.remove(&k_bnd);
+        ab.minor_collect(&roots);
+        assert_eq!(ab.young_len(), 1);
+        assert_eq!(ab.take_cleared_weak(), vec![(m_bnd, k_bnd)]);
+    }
+
+    #[test]
+    fn test_weak_compact() {
+        let mut ab = AllocBox::with_config(GcConfig { compact: true, ..GcConfig::default() });
+        let (_, m_ptr, m_bnd) = test_utils::make_str("weakmap");
+        let (_, k_ptr, k_bnd) = test_utils::make_str("key");
+        let (_, v_ptr, v_bnd) = test_utils::make_str("value");
+        ab.alloc(m_bnd.clone(), m_ptr).unwrap();
+        ab.alloc(k_bnd.clone(), k_ptr).unwrap();
+        ab.alloc(v_bnd.clone(), v_ptr).unwrap();
+        ab.set_ephemeron(m_bnd.clone(), k_bnd.clone(), v_bnd.clone());
+        ab.add_weak_ref(m_bnd.clone(), k_bnd.clone());
+
+        let mut roots = HashSet::new();
+        roots.insert(m_bnd.clone());
+        roots.insert(k_bnd.clone());
+        ab.collect(&roots);
+        let (m, k, v) = (ab.forwarding()[&m_bnd].clone(),
+                         ab.forwarding()[&k_bnd].clone(),
+                         ab.forwarding()[&v_bnd].clone());
+        assert_eq!(ab.ephemeron(&m, &k), Some(&v));
+        assert!(ab.weak_refs(&m).unwrap().contains(&k));
+    }
+
+    #[test]
+    fn test_has_room() {
+        let mut ab = AllocBox::new();
+        assert!(ab.has_room(usize::max_value() / 2, usize::max_value() / 2));
+        ab.set_policy(GcPolicy {
+            max_objects: Some(1),
+            max_bytes: Some(1024),
+            ..GcPolicy::default()
+        });
+        assert!(ab.has_room(1, 0));
+        assert!(!ab.has_room(2, 0));
+        assert!(!ab.has_room(0, 1025));
+        let (_, x_ptr, _) = test_utils::make_str("x");
+        ab.alloc(Binding::anon(), x_ptr).unwrap();
+        assert!(!ab.has_room(1, 0));
+        assert!(ab.has_room(0, 0));
+    }
+
+    #[test]
+    fn test_register_roots() {
+        let mut ab = AllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let roots = Rc::new(RefCell::new(HashSet::new()));
+        roots.borrow_mut().insert(x_bnd.clone());
+        ab.register_roots(&roots);
+
+        ab.collect(&HashSet::new());
+        assert!(ab.find_id(&x_bnd).is_some());
+        drop(roots);
+        ab.collect(&HashSet::new());
+        assert!(ab.is_empty());
+    }
+
+    #[test]
+    fn test_hard_limits() {
+        let mut ab = AllocBox::new();
+        ab.set_policy(GcPolicy { max_objects: Some(1), ..GcPolicy::default() });
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        assert!(matches!(ab.alloc(y_bnd.clone(), y_ptr), Err(GcError::OutOfMemory)));
+        assert!(ab.find_id(&y_bnd).is_none());
+
+        let max_bytes = ab.byte_len();
+        ab.set_policy(GcPolicy { max_bytes: Some(max_bytes), ..GcPolicy::default() });
+        let (_, big_ptr, _) = test_utils::make_str("a string that no longer fits in the heap");
+        assert!(matches!(ab.update_ptr(&x_bnd, big_ptr.clone()), Err(GcError::OutOfMemory)));
+        assert_eq!(ab.byte_len(), max_bytes);
+
+        // Growth in place is kept, but reported
+        *ab.find_id(&x_bnd).unwrap().borrow_mut() = big_ptr;
+        assert!(matches!(ab.mutated(&x_bnd, max_bytes), Err(GcError::OutOfMemory)));
+        assert!(ab.byte_len() > max_bytes);
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut ab = AllocBox::with_config(GcConfig {
+            nursery: Some((2, 4)),
+            ..GcConfig::default()
+        });
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert_eq!(ab.young_len(), 1);
+
+        // Dropping the nursery keeps its allocations alive in the old generation
+        ab.configure(GcConfig::default());
+        assert_eq!(ab.young_len(), 0);
+        assert!(ab.white_set.contains_key(&x_bnd));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut ab = AllocBox::with_config(GcConfig { stats: true, ..GcConfig::default() });
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        ab.alloc(Binding::anon(), y_ptr).unwrap();
+        let peak_bytes = ab.byte_len();
+
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd);
+        ab.collect(&roots);
+
+        let stats = ab.stats();
+        assert_eq!(stats.collections, 1);
+        assert_eq!(stats.cycles.len(), 1);
+        assert_eq!(stats.cycles[0].kind, CollectionKind::Full);
+        assert_eq!(stats.cycles[0].marked, 1);
+        assert_eq!(stats.cycles[0].swept, 1);
+        assert_eq!(stats.live.strs, 1);
+        assert_eq!(stats.live.objs, 0);
+        assert_eq!(stats.peak_objects, 2);
+        assert_eq!(stats.peak_bytes, peak_bytes);
+
+        ab.reset_stats();
+        let stats = ab.stats();
+        assert_eq!(stats.collections, 0);
+        assert!(stats.cycles.is_empty());
+        assert_eq!(stats.peak_objects, 1);
+        assert_eq!(stats.live.strs, 1);
+    }
+
+    #[test]
+    fn test_stats_minor_and_incremental() {
+        let mut ab = AllocBox::with_config(GcConfig {
+            nursery: Some((4, 4)),
+            stats: true,
+            ..GcConfig::default()
+        });
+        let (_, x_ptr, _) = test_utils::make_str("x");
+        ab.alloc(Binding::anon(), x_ptr).unwrap();
+        ab.minor_collect(&HashSet::new());
+        ab.start_cycle(&HashSet::new());
+        ab.mark_step(1);
+        ab.finish_cycle();
+
+        let stats = ab.stats();
+        assert_eq!(stats.collections, 2);
+        assert_eq!(stats.cycles[0].kind, CollectionKind::Minor);
+        assert_eq!(stats.cycles[0].swept, 1);
+        assert_eq!(stats.cycles[1].kind, CollectionKind::Incremental);
+    }
+
+    #[test]
+    fn test_stats_disabled() {
+        let mut ab = AllocBox::new();
+        ab.collect(&HashSet::new());
+        let stats = ab.stats();
+        // Collections are always counted, but cycles are only recorded on request
+        assert_eq!(stats.collections, 1);
+        assert!(stats.cycles.is_empty());
+    }
+
+    #[test]
+    fn test_write_heap_snapshot() {
+        let heap = test_utils::make_alloc_box();
+        let (s, s_ptr, _) = test_utils::make_str("a \"quoted\" string");
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        // Garbage is included too; the snapshot shows the heap as it is
+        let (_, g_ptr, _) = test_utils::make_str("garbage");
+        heap.borrow_mut().alloc(Binding::anon(), g_ptr).unwrap();
+
+        let mut out = Vec::new();
+        let roots = vec![("a".to_string(), a_bnd)];
+        heap.borrow().write_heap_snapshot(&roots, &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.starts_with("{\"snapshot\":{\"meta\":{"));
+        // The synthetic root plus three heap objects
+        assert!(json.contains("\"node_count\":4,"));
+        // root -> a, a -> s
+        assert!(json.contains("\"edge_count\":2,"));
+        assert!(json.contains("\"(GC roots)\""));
+        assert!(json.contains("\"a \\\"quoted\\\" string\""));
+        assert!(json.ends_with("]}"));
+    }
+
+    #[test]
+    fn test_color_of() {
+        let heap = test_utils::make_alloc_box();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("s");
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        assert_eq!(heap.borrow().color_of(&a_bnd), Some(Color::White));
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().start_cycle(&roots);
+        assert_eq!(heap.borrow().color_of(&a_bnd), Some(Color::Black));
+        assert_eq!(heap.borrow().color_of(&s_bnd), Some(Color::Grey));
+        assert_eq!(heap.borrow().color_of(&Binding::anon()), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        let heap = test_utils::make_alloc_box();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("s");
+        let (a, a_ptr, a_bnd) = test_utils::make_obj(vec![(key("s"), s, Some(s_ptr))], heap.clone());
+        heap.borrow_mut().alloc(a.binding, a_ptr).unwrap();
+        let mut report = VerifyReport::new();
+        heap.borrow().verify_into(&mut report);
+        assert!(report.is_ok());
+
+        // Force a black -> white edge, and a binding in two sets at once
+        {
+            let mut ab = heap.borrow_mut();
+            let a_ptr = ab.white_set.remove(&a_bnd).unwrap();
+            ab.black_set.insert(a_bnd.clone(), a_ptr.clone());
+            ab.grey_set.insert(a_bnd.clone(), a_ptr);
+        }
+        let mut report = VerifyReport::new();
+        heap.borrow().verify_into(&mut report);
+        assert_eq!(report.violations.len(), 2);
+        assert!(report.violations.contains(&Violation::MultipleColors(a_bnd.clone())));
+        assert!(report.violations.contains(&Violation::BlackToWhite(a_bnd, s_bnd)));
+    }
+
+    #[test]
+    fn test_verify_mid_cycle() {
+        let heap = test_utils::make_alloc_box();
+        let (_, a_ptr, a_bnd) = test_utils::make_obj(Vec::new(), heap.clone());
+        let (s, s_ptr, s_bnd) = test_utils::make_str("s");
+        heap.borrow_mut().alloc(a_bnd.clone(), a_ptr).unwrap();
+        heap.borrow_mut().alloc(s_bnd.clone(), s_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(a_bnd.clone());
+        heap.borrow_mut().start_cycle(&roots);
+        assert_eq!(heap.borrow().color_of(&a_bnd), Some(Color::Black));
+
+        // Point the black object at the white string, and verify before the
+        // write barrier has run
+        {
+            let handle = GcRefMut::new(&heap, a_bnd.clone());
+            if let JsPtrEnum::JsObj(ref mut obj) = *handle.borrow_mut() {
+                obj.dict.insert(key("s"), s);
+            };
+            let mut report = VerifyReport::new();
+            heap.borrow().verify_into(&mut report);
+            assert!(report.is_ok());
+        }
+        loop {
+            let done = heap.borrow_mut().mark_step(1);
+            let mut report = VerifyReport::new();
+            heap.borrow().verify_into(&mut report);
+            assert!(report.is_ok());
+            if done { break; }
+        }
+        heap.borrow_mut().finish_cycle();
+        assert!(heap.borrow().find_id(&s_bnd).is_some());
+    }
+
+    #[test]
+    fn test_collect_traces_fn_env() {
+        let mut ab = AllocBox::new();
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("captured");
+        ab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        ab.set_fn_env(f_bnd.clone(), env);
+
+        let mut roots = HashSet::new();
+        roots.insert(f_bnd.clone());
+        ab.collect(&roots);
+        assert!(ab.find_id(&x_bnd).is_some());
+
+        // Once the function dies, so does everything it captured
+        ab.collect(&HashSet::new());
+        assert!(ab.is_empty());
+        assert!(ab.fn_env(&f_bnd).is_none());
+    }
+
+    #[test]
+    fn test_fn_children() {
+        let mut ab = AllocBox::new();
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("captured");
+        ab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        ab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert!(ab.children(&f_bnd).is_empty());
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        ab.set_fn_env(f_bnd.clone(), env.clone());
+        assert_eq!(ab.children(&f_bnd), env);
+
+        // An incremental cycle reaches the captured binding only through the
+        // function
+        let mut roots = HashSet::new();
+        roots.insert(f_bnd.clone());
+        ab.start_cycle(&roots);
+        while !ab.mark_step(1) {}
+        ab.finish_cycle();
+        assert!(ab.find_id(&x_bnd).is_some());
+    }
 }
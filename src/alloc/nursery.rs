@@ -0,0 +1,62 @@
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+
+use js_types::js_var::JsPtrEnum;
+use js_types::binding::Binding;
+
+use super::Alloc;
+
+/// The young generation of an `AllocBox`.
+/// young: Every allocation that has not yet survived enough minor collections
+///        to be promoted into the old generation.
+/// ages: The number of minor collections each young allocation has survived.
+/// remembered: Old allocations that may hold pointers into the nursery. Their
+///             children are treated as extra roots by a minor collection.
+/// promote_age: How many minor collections an allocation must survive before
+///              it is promoted.
+/// major_interval: How many minor collections may run before a full
+///                 collection is forced, so the old generation is reclaimed too.
+pub struct Nursery {
+    pub young: HashMap<Binding, Alloc<JsPtrEnum>>,
+    pub ages: HashMap<Binding, usize>,
+    pub remembered: HashSet<Binding>,
+    pub promote_age: usize,
+    pub major_interval: usize,
+    pub minors_since_major: usize,
+}
+
+impl Nursery {
+    pub fn new(promote_age: usize, major_interval: usize) -> Nursery {
+        Nursery {
+            young: HashMap::new(),
+            ages: HashMap::new(),
+            remembered: HashSet::new(),
+            promote_age: promote_age,
+            major_interval: major_interval,
+            minors_since_major: 0,
+        }
+    }
+
+    pub fn is_young(&self, bnd: &Binding) -> bool {
+        self.young.contains_key(bnd)
+    }
+
+    pub fn insert(&mut self, bnd: Binding, ptr: Alloc<JsPtrEnum>) {
+        self.ages.insert(bnd.clone(), 0);
+        self.young.insert(bnd, ptr);
+    }
+
+    pub fn remove(&mut self, bnd: &Binding) -> Option<Alloc<JsPtrEnum>> {
+        self.ages.remove(bnd);
+        self.young.remove(bnd)
+    }
+
+    /// Empty the nursery, returning every young allocation so it can be
+    /// promoted wholesale ahead of a full collection.
+    pub fn evacuate(&mut self) -> HashMap<Binding, Alloc<JsPtrEnum>> {
+        self.ages.clear();
+        self.remembered.clear();
+        self.minors_since_major = 0;
+        self.young.drain().collect()
+    }
+}
@@ -0,0 +1,78 @@
+use std::mem;
+
+use js_types::js_var::{JsKey, JsPtrEnum, JsVar};
+
+/// Controls when an `AllocBox` asks for a collection.
+/// object_threshold: Request a collection once more than this many objects
+///                   are allocated.
+/// byte_threshold: Request a collection once the estimated heap size exceeds
+///                 this many bytes.
+/// growth_factor: After a collection, the next limits are the surviving heap
+///                size times this factor, but never lower than the thresholds.
+/// min_interval: The minimum number of allocations between two requested
+///               collections, so a heap full of live data doesn't thrash.
+/// max_objects: A hard limit on the number of live objects, if any.
+/// max_bytes: A hard limit on the estimated heap size in bytes, if any.
+#[derive(Clone, Debug)]
+pub struct GcPolicy {
+    pub object_threshold: usize,
+    pub byte_threshold: usize,
+    pub growth_factor: f64,
+    pub min_interval: usize,
+    pub max_objects: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for GcPolicy {
+    fn default() -> GcPolicy {
+        GcPolicy {
+            object_threshold: 4096,
+            byte_threshold: 1 << 20,
+            growth_factor: 2.0,
+            min_interval: 64,
+            max_objects: None,
+            max_bytes: None,
+        }
+    }
+}
+
+/// Everything needed to configure a heap.
+/// policy: When to collect, and the hard limits on heap size.
+/// nursery: If set, the `(promote_age, major_interval)` of a generational
+///          heap. See `AllocBox::with_nursery`.
+/// trace: Log a summary of every collection to stderr.
+/// stats: Record per-collection metrics, see `AllocBox::stats`.
+/// compact: Compact the heap after every full or incremental collection. An
+///          `AllocBox` moves its survivors to new bindings, see
+///          `AllocBox::forwarding`; a `SlabAllocBox` slides them together
+///          and they keep their bindings, see `SlabAllocBox::compact`.
+#[derive(Clone, Debug, Default)]
+pub struct GcConfig {
+    pub policy: GcPolicy,
+    pub nursery: Option<(usize, usize)>,
+    pub trace: bool,
+    pub stats: bool,
+    pub compact: bool,
+}
+
+impl GcPolicy {
+    /// The limit to use after a collection leaves `live` units on the heap.
+    pub fn next_limit(&self, live: usize, threshold: usize) -> usize {
+        let grown = (live as f64 * self.growth_factor) as usize;
+        if grown > threshold { grown } else { threshold }
+    }
+}
+
+/// Approximate the number of bytes a heap allocation occupies.
+pub fn size_of_ptr(ptr: &JsPtrEnum) -> usize {
+    mem::size_of::<JsPtrEnum>() + match *ptr {
+        JsPtrEnum::JsStr(ref s) => s.text.len(),
+        JsPtrEnum::JsObj(ref obj) => obj.dict.len() * size_of_prop(),
+        _ => 0,
+    }
+}
+
+/// Approximate the number of bytes a property adds to an object allocation.
+pub fn size_of_prop() -> usize {
+    mem::size_of::<JsKey>() + mem::size_of::<JsVar>()
+}
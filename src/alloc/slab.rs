@@ -0,0 +1,698 @@
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+use std::rc::Rc;
+use std::time::Instant;
+
+use gc_error::{GcError, Result};
+use js_types::js_var::JsPtrEnum;
+use js_types::allocator::Allocator;
+use js_types::binding::Binding;
+use verify::VerifyReport;
+
+use super::{AllocBox, Color, GcConfig, GcPolicy, GcStats, Heap};
+use super::accounting::Accounting;
+use super::policy::size_of_ptr;
+use super::stats::{CollectionKind, CycleStats};
+
+// An occupied slot of the slab.
+struct Entry {
+    binding: Binding,
+    ptr: RefCell<JsPtrEnum>,
+    // The epoch of the last collection that reached this allocation. A new
+    // allocation starts out with the current epoch, which the next collection
+    // flips away from, so it is unmarked until that collection reaches it.
+    mark: u8,
+}
+
+/// An alternative heap storage engine to `AllocBox`. Allocations live in a
+/// slab of slots that are reused through a free list, and are never moved
+/// between maps: each slot carries a mark byte, and a collection flips the
+/// heap's mark epoch, which unmarks every allocation at once. Sweeping frees
+/// the slots not marked with the current epoch.
+///
+/// Values are stored inline in their slots rather than boxed individually, so
+/// `compact` can slide them together into contiguous storage. Their bindings
+/// never change.
+///
+/// Like `AllocBox`, the slab requests collections and enforces hard limits by
+/// its `GcPolicy`, keeps statistics, and collects from the root sets
+/// registered with it. Collections are stop-the-world only, though. There is
+/// no nursery, so `collect_garbage` always runs a full collection and the
+/// nursery setting of a `GcConfig` is ignored. There is no incremental
+/// marking: `start_cycle` only records its roots, `mark_step` does no work,
+/// `finish_cycle` runs a full collection, and `shade` has nothing to do.
+/// Weak references and `WeakMap` entries aren't supported.
+pub struct SlabAllocBox {
+    slots: Vec<Option<Entry>>,
+    free_slots: Vec<usize>,
+    index: HashMap<Binding, usize>,
+    fn_envs: HashMap<Binding, HashSet<Binding>>,
+    epoch: u8,
+    // The policy, statistics and registered root sets, kept as by `AllocBox`.
+    accounting: Accounting,
+    // Whether collections end by compacting the slab.
+    compact: bool,
+    // The roots given to `start_cycle`, until `finish_cycle` collects from them.
+    cycle_roots: Option<HashSet<Binding>>,
+}
+
+impl Allocator for SlabAllocBox {
+    type Error = GcError;
+
+    fn alloc(&mut self, binding: Binding, ptr: JsPtrEnum) -> Result<()> {
+        if self.index.contains_key(&binding) {
+            return Err(GcError::Alloc(binding));
+        }
+        let size = size_of_ptr(&ptr);
+        if !self.has_room(1, size) {
+            return Err(GcError::OutOfMemory);
+        }
+        let entry = Entry {
+            binding: binding.clone(),
+            ptr: RefCell::new(ptr),
+            mark: self.epoch,
+        };
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(entry);
+                slot
+            }
+            None => {
+                self.slots.push(Some(entry));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(binding, slot);
+        let len = self.len();
+        self.accounting.allocated(len, size);
+        Ok(())
+    }
+}
+
+impl Heap for SlabAllocBox {
+    fn find(&self, bnd: &Binding) -> Option<&RefCell<JsPtrEnum>> {
+        self.find_id(bnd)
+    }
+
+    fn entries(&self) -> Vec<(&Binding, &RefCell<JsPtrEnum>)> {
+        self.slots.iter()
+                  .filter_map(|entry| entry.as_ref())
+                  .map(|entry| (&entry.binding, &entry.ptr))
+                  .collect()
+    }
+
+    fn children(&self, bnd: &Binding) -> HashSet<Binding> {
+        match self.find_id(bnd) {
+            Some(ptr) => AllocBox::get_ptr_children(ptr, self.fn_envs.get(bnd)),
+            None => HashSet::new(),
+        }
+    }
+
+    // Every allocation is unmarked between collections.
+    fn color_of(&self, bnd: &Binding) -> Option<Color> {
+        self.find_id(bnd).map(|_| Color::White)
+    }
+
+    fn len(&self) -> usize {
+        SlabAllocBox::len(self)
+    }
+
+    fn byte_len(&self) -> usize {
+        SlabAllocBox::byte_len(self)
+    }
+
+    fn update_ptr(&mut self, binding: &Binding, ptr: JsPtrEnum) -> Result<()> {
+        SlabAllocBox::update_ptr(self, binding, ptr)
+    }
+
+    fn mutated(&mut self, binding: &Binding, old_size: usize) -> Result<()> {
+        SlabAllocBox::mutated(self, binding, old_size)
+    }
+
+    // No collection is ever in progress between calls, so no root can be
+    // missed by one.
+    fn shade(&mut self, _binding: &Binding) {}
+
+    fn set_fn_env(&mut self, fn_bnd: Binding, env: HashSet<Binding>) {
+        SlabAllocBox::set_fn_env(self, fn_bnd, env)
+    }
+
+    fn recapture(&mut self, old: &Binding, new: &Binding) {
+        SlabAllocBox::recapture(self, old, new)
+    }
+
+    fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>) {
+        SlabAllocBox::register_roots(self, roots)
+    }
+
+    fn collect(&mut self, roots: &HashSet<Binding>) {
+        SlabAllocBox::collect(self, roots)
+    }
+
+    fn collect_garbage(&mut self, roots: &HashSet<Binding>) {
+        SlabAllocBox::collect(self, roots)
+    }
+
+    fn start_cycle(&mut self, roots: &HashSet<Binding>) {
+        SlabAllocBox::start_cycle(self, roots)
+    }
+
+    fn mark_step(&mut self, _budget: usize) -> bool {
+        true
+    }
+
+    fn finish_cycle(&mut self) {
+        SlabAllocBox::finish_cycle(self)
+    }
+
+    fn gc_requested(&self) -> bool {
+        SlabAllocBox::gc_requested(self)
+    }
+
+    fn has_room(&self, objects: usize, bytes: usize) -> bool {
+        SlabAllocBox::has_room(self, objects, bytes)
+    }
+
+    fn sweeps(&self) -> usize {
+        SlabAllocBox::sweeps(self)
+    }
+
+    fn configure(&mut self, config: GcConfig) {
+        SlabAllocBox::configure(self, config)
+    }
+
+    fn stats(&self) -> GcStats {
+        SlabAllocBox::stats(self)
+    }
+
+    fn reset_stats(&mut self) {
+        SlabAllocBox::reset_stats(self)
+    }
+
+    // The slab has no color sets or generations, so there is nothing of its
+    // own to check.
+    fn verify_into(&self, _report: &mut VerifyReport) {}
+}
+
+impl SlabAllocBox {
+    pub fn new() -> SlabAllocBox {
+        SlabAllocBox {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            index: HashMap::new(),
+            fn_envs: HashMap::new(),
+            epoch: 0,
+            accounting: Accounting::new(),
+            compact: false,
+            cycle_roots: None,
+        }
+    }
+
+    pub fn with_config(config: GcConfig) -> SlabAllocBox {
+        let mut slab = SlabAllocBox::new();
+        slab.configure(config);
+        slab
+    }
+
+    /// Apply a configuration. The slab has no nursery, so `config.nursery`
+    /// is ignored.
+    pub fn configure(&mut self, config: GcConfig) {
+        self.compact = config.compact;
+        let len = self.len();
+        self.accounting.configure(&config, len);
+    }
+
+    /// A snapshot of the collector's statistics, with live object counts
+    /// computed from the current contents of the heap.
+    pub fn stats(&self) -> GcStats {
+        self.accounting.stats(self.slots.iter().filter_map(|entry| entry.as_ref()).map(|entry| &entry.ptr))
+    }
+
+    /// Clear all statistics. Peaks restart from the current heap size.
+    pub fn reset_stats(&mut self) {
+        let len = self.len();
+        self.accounting.reset_stats(len);
+    }
+
+    pub fn policy(&self) -> &GcPolicy {
+        self.accounting.policy()
+    }
+
+    pub fn set_policy(&mut self, policy: GcPolicy) {
+        let len = self.len();
+        self.accounting.set_policy(policy, len);
+    }
+
+    /// Whether the heap has grown past the limits set by its `GcPolicy` since
+    /// the last collection.
+    pub fn gc_requested(&self) -> bool {
+        self.accounting.gc_requested()
+    }
+
+    /// Whether `objects` more allocations totalling `bytes` would fit within the
+    /// hard limits of the heap's `GcPolicy`.
+    pub fn has_room(&self, objects: usize, bytes: usize) -> bool {
+        self.accounting.has_room(self.len(), objects, bytes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of slots in the slab, free or not.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The estimated size of the heap in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.accounting.bytes()
+    }
+
+    /// The number of collections this heap has run. Unlike the stats, it is
+    /// never reset.
+    pub fn sweeps(&self) -> usize {
+        self.accounting.sweeps()
+    }
+
+    pub fn find_id(&self, bnd: &Binding) -> Option<&RefCell<JsPtrEnum>> {
+        match self.index.get(bnd) {
+            Some(&slot) => self.slots[slot].as_ref().map(|entry| &entry.ptr),
+            None => None,
+        }
+    }
+
+    pub fn update_ptr(&mut self, binding: &Binding, ptr: JsPtrEnum) -> Result<()> {
+        let old_size = match self.find_id(binding) {
+            Some(alloc) => size_of_ptr(&*alloc.borrow()),
+            None => return Err(GcError::HeapUpdate),
+        };
+        let new_size = size_of_ptr(&ptr);
+        if !self.has_room(0, new_size.saturating_sub(old_size)) {
+            return Err(GcError::OutOfMemory);
+        }
+        if let Some(alloc) = self.find_id(binding) {
+            *alloc.borrow_mut() = ptr;
+        }
+        let len = self.len();
+        self.accounting.resized(len, old_size, new_size);
+        Ok(())
+    }
+
+    /// Account for an allocation that was mutated in place, given its size
+    /// before the mutation. As for `AllocBox::mutated`, growth past the hard
+    /// limits is kept, but reported as `GcError::OutOfMemory`.
+    pub fn mutated(&mut self, binding: &Binding, old_size: usize) -> Result<()> {
+        let new_size = match self.find_id(binding) {
+            Some(alloc) => size_of_ptr(&*alloc.borrow()),
+            None => return Ok(()),
+        };
+        let len = self.len();
+        self.accounting.resized(len, old_size, new_size);
+        if self.has_room(0, 0) { Ok(()) } else { Err(GcError::OutOfMemory) }
+    }
+
+    /// Free an allocation immediately, without waiting for a collection.
+    /// Returns whether anything was freed.
+    pub fn free(&mut self, binding: &Binding) -> bool {
+        match self.index.get(binding).cloned() {
+            Some(slot) => {
+                self.free_slot(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_fn_env(&mut self, fn_bnd: Binding, env: HashSet<Binding>) {
+        self.fn_envs.insert(fn_bnd, env);
+    }
+
+    /// Make every closure environment that captures `old` capture `new`
+    /// instead, as when a captured variable moves to a new binding.
+    pub fn recapture(&mut self, old: &Binding, new: &Binding) {
+        for env in self.fn_envs.values_mut() {
+            if env.remove(old) {
+                env.insert(new.clone());
+            }
+        }
+    }
+
+    /// Register a root set that every collection of this heap starts from, on
+    /// top of the roots it is given, until the set is dropped.
+    pub fn register_roots(&mut self, roots: &Rc<RefCell<HashSet<Binding>>>) {
+        self.accounting.register_roots(roots);
+    }
+
+    /// Run a full collection, freeing every allocation not reachable from
+    /// `roots` or the registered root sets.
+    pub fn collect(&mut self, roots: &HashSet<Binding>) {
+        self.collect_as(roots, CollectionKind::Full);
+    }
+
+    /// Record the roots of a collection to be run by `finish_cycle`. The slab
+    /// can't mark incrementally, so no work is done until then.
+    pub fn start_cycle(&mut self, roots: &HashSet<Binding>) {
+        self.cycle_roots = Some(roots.clone());
+    }
+
+    /// Run the collection begun by `start_cycle`, stopping the world for all
+    /// of it.
+    pub fn finish_cycle(&mut self) {
+        let roots = self.cycle_roots.take().unwrap_or_else(HashSet::new);
+        self.collect_as(&roots, CollectionKind::Incremental);
+    }
+
+    /// Slide every allocation down into the lowest free slots, so the live
+    /// part of the slab is contiguous, and release the slots above it.
+    pub fn compact(&mut self) {
+        let mut live = 0;
+        for slot in 0..self.slots.len() {
+            if self.slots[slot].is_none() { continue; }
+            if slot != live {
+                let entry = self.slots[slot].take();
+                if let Some(ref entry) = entry {
+                    self.index.insert(entry.binding.clone(), live);
+                }
+                self.slots[live] = entry;
+            }
+            live += 1;
+        }
+        self.slots.truncate(live);
+        self.slots.shrink_to_fit();
+        self.free_slots.clear();
+        self.free_slots.shrink_to_fit();
+    }
+
+    // Run a stop-the-world collection, recorded as a collection of `kind`.
+    fn collect_as(&mut self, roots: &HashSet<Binding>, kind: CollectionKind) {
+        let start = Instant::now();
+        let roots = self.accounting.all_roots(roots);
+        // Flipping the epoch unmarks every allocation without touching it
+        self.epoch ^= 1;
+        let epoch = self.epoch;
+        let mut worklist: Vec<usize> = roots.iter()
+                                            .filter_map(|bnd| self.index.get(bnd).cloned())
+                                            .collect();
+        let mut marked = 0;
+        while let Some(slot) = worklist.pop() {
+            let children = match self.slots[slot] {
+                Some(ref mut entry) => {
+                    if entry.mark == epoch { continue; }
+                    entry.mark = epoch;
+                    AllocBox::get_ptr_children(&entry.ptr, self.fn_envs.get(&entry.binding))
+                }
+                None => continue,
+            };
+            marked += 1;
+            worklist.extend(children.iter().filter_map(|bnd| self.index.get(bnd).cloned()));
+        }
+        let mark_time = start.elapsed();
+
+        let start = Instant::now();
+        let dead: Vec<usize> = self.slots.iter()
+                                         .enumerate()
+                                         .filter(|&(_, entry)| match *entry {
+                                             Some(ref entry) => entry.mark != epoch,
+                                             None => false,
+                                         })
+                                         .map(|(slot, _)| slot)
+                                         .collect();
+        let swept = dead.len();
+        for slot in dead {
+            self.free_slot(slot);
+        }
+        if self.compact {
+            self.compact();
+        }
+        let len = self.len();
+        self.accounting.reset_triggers(len);
+        self.accounting.record_cycle(len, CycleStats {
+            kind: kind,
+            marked: marked,
+            swept: swept,
+            mark_time: mark_time,
+            sweep_time: start.elapsed(),
+        });
+    }
+
+    fn free_slot(&mut self, slot: usize) {
+        if let Some(entry) = self.slots[slot].take() {
+            self.accounting.freed(size_of_ptr(&*entry.ptr.borrow()));
+            self.index.remove(&entry.binding);
+            self.fn_envs.remove(&entry.binding);
+            self.free_slots.push(slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::hash_set::HashSet;
+    use std::rc::Rc;
+
+    use gc_error::GcError;
+    use js_types::allocator::Allocator;
+    use js_types::binding::Binding;
+    use js_types::js_obj::JsObjStruct;
+    use js_types::js_var::{JsKey, JsPtrEnum, JsPtrTag, JsType, JsVar};
+    use alloc::{GcConfig, GcPolicy, Heap};
+    use test_utils;
+
+    fn key(s: &str) -> JsKey {
+        JsKey::JsSym(s.to_string())
+    }
+
+    #[test]
+    fn test_alloc() {
+        let mut slab = SlabAllocBox::new();
+        assert!(slab.is_empty());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        assert!(slab.alloc(x_bnd.clone(), x_ptr.clone()).is_ok());
+        assert_eq!(slab.len(), 1);
+        assert!(slab.byte_len() > 0);
+        assert!(slab.find_id(&x_bnd).is_some());
+        assert!(matches!(slab.alloc(x_bnd, x_ptr), Err(GcError::Alloc(_))));
+    }
+
+    #[test]
+    fn test_update_ptr() {
+        let mut slab = SlabAllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let old_bytes = slab.byte_len();
+        let (_, y_ptr, _) = test_utils::make_str("yyyy");
+        assert!(slab.update_ptr(&x_bnd, y_ptr.clone()).is_ok());
+        assert!(slab.byte_len() > old_bytes);
+        assert_eq!(slab.len(), 1);
+        assert!(matches!(slab.update_ptr(&Binding::anon(), y_ptr), Err(GcError::HeapUpdate)));
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut slab = SlabAllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        slab.alloc(y_bnd.clone(), y_ptr).unwrap();
+
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd.clone());
+        slab.collect(&roots);
+        assert_eq!(slab.len(), 1);
+        assert!(slab.find_id(&x_bnd).is_some());
+        assert!(slab.find_id(&y_bnd).is_none());
+
+        // Survivors stay live across an epoch flip
+        slab.collect(&roots);
+        assert_eq!(slab.len(), 1);
+        slab.collect(&HashSet::new());
+        assert!(slab.is_empty());
+        assert_eq!(slab.byte_len(), 0);
+    }
+
+    #[test]
+    fn test_collect_graph() {
+        let heap = test_utils::make_alloc_box();
+        let mut slab = SlabAllocBox::new();
+        // a -> b -> a, with a string hanging off of b
+        let a = JsVar::new(JsType::JsPtr(JsPtrTag::JsObj));
+        let a_bnd = a.binding.clone();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("cycle");
+        let b_bnd = Binding::anon();
+        slab.alloc(s_bnd.clone(), s_ptr).unwrap();
+        let b_ptr = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("a"), a, None),
+                                                                         (key("s"), s, None)],
+                                                      &mut *heap.borrow_mut()));
+        slab.alloc(b_bnd.clone(), b_ptr).unwrap();
+        let mut b = JsVar::new(JsType::JsPtr(JsPtrTag::JsObj));
+        b.binding = b_bnd.clone();
+        let a_ptr = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("b"), b, None)],
+                                                      &mut *heap.borrow_mut()));
+        slab.alloc(a_bnd.clone(), a_ptr).unwrap();
+        assert_eq!(slab.len(), 3);
+
+        let mut roots = HashSet::new();
+        roots.insert(b_bnd.clone());
+        slab.collect(&roots);
+        assert_eq!(slab.len(), 3);
+        for bnd in &[&a_bnd, &b_bnd, &s_bnd] {
+            assert!(slab.find_id(bnd).is_some());
+        }
+
+        slab.collect(&HashSet::new());
+        assert!(slab.is_empty());
+    }
+
+    #[test]
+    fn test_collect_through_new_alloc() {
+        let heap = test_utils::make_alloc_box();
+        let mut slab = SlabAllocBox::new();
+        let (old, old_ptr, old_bnd) = test_utils::make_str("old");
+        slab.alloc(old_bnd.clone(), old_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(old_bnd.clone());
+        slab.collect(&roots);
+
+        // After the epoch flip, the only path to the old string is through an
+        // object allocated since
+        let obj_bnd = Binding::anon();
+        let obj_ptr = JsPtrEnum::JsObj(JsObjStruct::new(None, "test", vec![(key("old"), old, None)],
+                                                        &mut *heap.borrow_mut()));
+        slab.alloc(obj_bnd.clone(), obj_ptr).unwrap();
+        let mut roots = HashSet::new();
+        roots.insert(obj_bnd.clone());
+        slab.collect(&roots);
+        assert_eq!(slab.len(), 2);
+        assert!(slab.find_id(&obj_bnd).is_some());
+        assert!(slab.find_id(&old_bnd).is_some());
+    }
+
+    #[test]
+    fn test_collect_fn_env() {
+        let mut slab = SlabAllocBox::new();
+        let (_, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        slab.alloc(f_bnd.clone(), f_ptr).unwrap();
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        let mut env = HashSet::new();
+        env.insert(x_bnd.clone());
+        slab.set_fn_env(f_bnd.clone(), env);
+
+        let mut roots = HashSet::new();
+        roots.insert(f_bnd);
+        slab.collect(&roots);
+        assert!(slab.find_id(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_reuse_slots() {
+        let mut slab = SlabAllocBox::new();
+        for _ in 0..4 {
+            slab.alloc(Binding::anon(), test_utils::make_str("x").1).unwrap();
+        }
+        assert_eq!(slab.capacity(), 4);
+        slab.collect(&HashSet::new());
+        assert!(slab.is_empty());
+
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        slab.alloc(y_bnd.clone(), y_ptr).unwrap();
+        assert_eq!(slab.capacity(), 4);
+        assert!(slab.free(&y_bnd));
+        assert!(!slab.free(&y_bnd));
+        assert!(slab.is_empty());
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut slab = SlabAllocBox::new();
+        let mut roots = HashSet::new();
+        for i in 0..4 {
+            let (_, ptr, bnd) = test_utils::make_str(&format!("{}", i));
+            slab.alloc(bnd.clone(), ptr).unwrap();
+            if i % 2 == 1 { roots.insert(bnd); }
+        }
+        slab.collect(&roots);
+        assert_eq!(slab.capacity(), 4);
+
+        // The survivors keep their bindings and values, in a dense prefix
+        slab.compact();
+        assert_eq!(slab.capacity(), 2);
+        for bnd in &roots {
+            assert!(matches!(*slab.find_id(bnd).unwrap().borrow(), JsPtrEnum::JsStr(_)));
+        }
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert_eq!(slab.capacity(), 3);
+        slab.collect(&roots);
+        assert!(slab.find_id(&x_bnd).is_none());
+        assert_eq!(slab.len(), 2);
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut slab = SlabAllocBox::with_config(GcConfig {
+            policy: GcPolicy {
+                object_threshold: 1,
+                min_interval: 1,
+                max_objects: Some(2),
+                ..GcPolicy::default()
+            },
+            stats: true,
+            ..GcConfig::default()
+        });
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        assert!(!slab.gc_requested());
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        slab.alloc(Binding::anon(), y_ptr).unwrap();
+        assert!(slab.gc_requested());
+        let (_, z_ptr, z_bnd) = test_utils::make_str("z");
+        assert!(matches!(slab.alloc(z_bnd.clone(), z_ptr), Err(GcError::OutOfMemory)));
+        assert!(slab.find_id(&z_bnd).is_none());
+
+        let mut roots = HashSet::new();
+        roots.insert(x_bnd);
+        slab.collect(&roots);
+        assert!(!slab.gc_requested());
+        let stats = slab.stats();
+        assert_eq!(stats.collections, 1);
+        assert_eq!(stats.cycles[0].marked, 1);
+        assert_eq!(stats.cycles[0].swept, 1);
+        assert_eq!(stats.peak_objects, 2);
+        assert_eq!(stats.live.strs, 1);
+    }
+
+    #[test]
+    fn test_cycle_runs_full_collection() {
+        let mut slab = SlabAllocBox::new();
+        let (_, x_ptr, x_bnd) = test_utils::make_str("x");
+        let (_, y_ptr, y_bnd) = test_utils::make_str("y");
+        slab.alloc(x_bnd.clone(), x_ptr).unwrap();
+        slab.alloc(y_bnd.clone(), y_ptr).unwrap();
+        let roots = Rc::new(RefCell::new(HashSet::new()));
+        roots.borrow_mut().insert(x_bnd.clone());
+        slab.register_roots(&roots);
+
+        // Nothing is marked or swept until the cycle is finished
+        slab.start_cycle(&HashSet::new());
+        assert!(slab.mark_step(1));
+        assert_eq!(slab.len(), 2);
+        slab.finish_cycle();
+        assert!(slab.find_id(&x_bnd).is_some());
+        assert!(slab.find_id(&y_bnd).is_none());
+        assert_eq!(slab.sweeps(), 1);
+
+        // There's no nursery, so every collection is a full one
+        drop(roots);
+        slab.collect_garbage(&HashSet::new());
+        assert!(slab.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::hash_map::HashMap;
+use std::io::{self, Write};
+
+use js_types::js_var::JsPtrEnum;
+use js_types::binding::Binding;
+
+use super::Heap;
+use super::policy::size_of_ptr;
+
+// Indices into the `node_types` and `edge_types` tables of the snapshot meta.
+const NODE_HIDDEN: usize = 0;
+const NODE_STRING: usize = 2;
+const NODE_OBJECT: usize = 3;
+const NODE_CLOSURE: usize = 5;
+const NODE_SYNTHETIC: usize = 9;
+const EDGE_PROPERTY: usize = 2;
+
+const NODE_FIELD_COUNT: usize = 6;
+
+const META: &'static str = r#"{"node_fields":["type","name","id","self_size","edge_count","trace_node_id"],"node_types":[["hidden","array","string","object","code","closure","regexp","number","native","synthetic","concatenated string","sliced string"],"string","number","number","number","number"],"edge_fields":["type","name_or_index","to_node"],"edge_types":[["context","element","property","internal","hidden","shortcut","weak"],"string_or_number","node"],"trace_function_info_fields":["function_id","name","script_name","script_id","line","column"],"trace_node_fields":["id","function_info_index","count","size","children"],"sample_fields":["timestamp_us","last_assigned_id"],"location_fields":["object_index","script_id","line","column"]}"#;
+
+// The string table of a snapshot. Every name is stored once and referred to
+// by its index.
+struct Strings {
+    indices: HashMap<String, usize>,
+    strings: Vec<String>,
+}
+
+impl Strings {
+    fn new() -> Strings {
+        Strings { indices: HashMap::new(), strings: Vec::new() }
+    }
+
+    fn index(&mut self, s: &str) -> usize {
+        if let Some(i) = self.indices.get(s) {
+            return *i;
+        }
+        let i = self.strings.len();
+        self.indices.insert(s.to_owned(), i);
+        self.strings.push(s.to_owned());
+        i
+    }
+}
+
+/// Serialise `heap` in the V8 `.heapsnapshot` format, so it can be
+/// loaded into the Memory tab of Chrome DevTools. `roots` pairs each root
+/// binding with the name it should be shown under, and becomes the edges of
+/// a synthetic `(GC roots)` node.
+pub fn write_heap_snapshot<H: Heap, W: Write>(heap: &H, roots: &[(String, Binding)], out: &mut W) -> io::Result<()> {
+    let allocs = heap.entries();
+    let mut strings = Strings::new();
+    // Node 0 is the synthetic root; heap objects follow in order.
+    let node_index: HashMap<&Binding, usize> = allocs.iter()
+                                                     .enumerate()
+                                                     .map(|(i, &(bnd, _))| (bnd, i + 1))
+                                                     .collect();
+
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+
+    let root_edges: Vec<_> = roots.iter()
+                                  .filter_map(|&(ref name, ref bnd)|
+                                              node_index.get(bnd).map(|i| (name, *i)))
+                                  .collect();
+    nodes.extend_from_slice(&[NODE_SYNTHETIC, strings.index("(GC roots)"), 1, 0,
+                              root_edges.len(), 0]);
+    for (name, i) in root_edges {
+        edges.extend_from_slice(&[EDGE_PROPERTY, strings.index(name), i * NODE_FIELD_COUNT]);
+    }
+
+    for (i, &(bnd, alloc)) in allocs.iter().enumerate() {
+        let ptr = alloc.borrow();
+        let (node_type, name) = match *ptr {
+            JsPtrEnum::JsStr(ref s) => (NODE_STRING, s.text.clone()),
+            JsPtrEnum::JsObj(_) => (NODE_OBJECT, format!("Object {}", bnd)),
+            JsPtrEnum::JsFn(_) => (NODE_CLOSURE, format!("Function {}", bnd)),
+            _ => (NODE_HIDDEN, format!("{}", bnd)),
+        };
+        let children: Vec<_> = heap.children(bnd)
+                                   .into_iter()
+                                   .filter_map(|child| node_index.get(&child).map(|j| (child.clone(), *j)))
+                                   .collect();
+        nodes.extend_from_slice(&[node_type, strings.index(&name), 2 * i + 3,
+                                  size_of_ptr(&*ptr), children.len(), 0]);
+        for (child, j) in children {
+            edges.extend_from_slice(&[EDGE_PROPERTY, strings.index(&format!("{}", child)),
+                                      j * NODE_FIELD_COUNT]);
+        }
+    }
+
+    try!(write!(out, r#"{{"snapshot":{{"meta":{},"node_count":{},"edge_count":{},"trace_function_count":0}},"#,
+                META, nodes.len() / NODE_FIELD_COUNT, edges.len() / 3));
+    try!(write!(out, r#""nodes":["#));
+    try!(write_numbers(out, &nodes));
+    try!(write!(out, r#"],"edges":["#));
+    try!(write_numbers(out, &edges));
+    try!(write!(out, r#"],"trace_function_infos":[],"trace_tree":[],"samples":[],"locations":[],"strings":["#));
+    for (i, s) in strings.strings.iter().enumerate() {
+        if i > 0 { try!(write!(out, ",")); }
+        try!(write_json_string(out, s));
+    }
+    write!(out, "]}}")
+}
+
+fn write_numbers<W: Write>(out: &mut W, ns: &[usize]) -> io::Result<()> {
+    for (i, n) in ns.iter().enumerate() {
+        if i > 0 { try!(write!(out, ",")); }
+        try!(write!(out, "{}", n));
+    }
+    Ok(())
+}
+
+fn write_json_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    try!(write!(out, "\""));
+    for c in s.chars() {
+        match c {
+            '"' => try!(write!(out, "\\\"")),
+            '\\' => try!(write!(out, "\\\\")),
+            '\n' => try!(write!(out, "\\n")),
+            '\r' => try!(write!(out, "\\r")),
+            '\t' => try!(write!(out, "\\t")),
+            c if (c as u32) < 0x20 => try!(write!(out, "\\u{:04x}", c as u32)),
+            c => try!(write!(out, "{}", c)),
+        }
+    }
+    write!(out, "\"")
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use js_types::js_var::JsPtrEnum;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CollectionKind {
+    Full,
+    Incremental,
+    Minor,
+}
+
+/// Metrics for a single collection.
+/// marked: Objects found live by the mark phase.
+/// swept: Objects freed by the sweep phase.
+/// mark_time: Time spent marking. For an incremental collection this is the
+///            sum over every step of the cycle.
+/// sweep_time: Time spent sweeping.
+#[derive(Clone, Debug)]
+pub struct CycleStats {
+    pub kind: CollectionKind,
+    pub marked: usize,
+    pub swept: usize,
+    pub mark_time: Duration,
+    pub sweep_time: Duration,
+}
+
+/// Live object counts, broken down by pointer type.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LiveCounts {
+    pub strs: usize,
+    pub objs: usize,
+    pub fns: usize,
+    pub other: usize,
+}
+
+impl LiveCounts {
+    pub fn count(&mut self, ptr: &JsPtrEnum) {
+        match *ptr {
+            JsPtrEnum::JsStr(_) => self.strs += 1,
+            JsPtrEnum::JsObj(_) => self.objs += 1,
+            JsPtrEnum::JsFn(_) => self.fns += 1,
+            _ => self.other += 1,
+        }
+    }
+}
+
+/// Collector statistics since the heap was created or last reset.
+/// collections: The number of collections of any kind.
+/// cycles: Per-collection metrics, oldest first. Only recorded while stats
+///         are enabled on the heap.
+/// live: Live objects by type, as of when the stats were queried.
+/// peak_objects: The largest number of objects the heap has held.
+/// peak_bytes: The largest estimated size of the heap in bytes.
+#[derive(Clone, Debug, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub cycles: Vec<CycleStats>,
+    pub live: LiveCounts,
+    pub peak_objects: usize,
+    pub peak_bytes: usize,
+}
@@ -0,0 +1,101 @@
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+use std::mem;
+
+use js_types::binding::Binding;
+
+use super::forwarded;
+
+/// The weak edges of an `AllocBox`, which the collector does not trace.
+/// refs: The allocations each owner refers to weakly, e.g. the target of a
+///       `WeakRef` or the members of a `WeakSet`.
+/// ephemerons: The entries of each `WeakMap` owner, from key to value. An
+///             entry keeps its value alive only while both the map and the
+///             key are alive.
+/// cleared: The weak references and entries dropped by collections because
+///          their target or key was freed, as `(owner, target)` pairs, oldest
+///          first.
+pub struct WeakTable {
+    pub refs: HashMap<Binding, HashSet<Binding>>,
+    pub ephemerons: HashMap<Binding, HashMap<Binding, Binding>>,
+    pub cleared: Vec<(Binding, Binding)>,
+}
+
+impl WeakTable {
+    pub fn new() -> WeakTable {
+        WeakTable {
+            refs: HashMap::new(),
+            ephemerons: HashMap::new(),
+            cleared: Vec::new(),
+        }
+    }
+
+    /// The values of the entries whose map and key are both `live`.
+    pub fn live_values<F>(&self, live: F) -> Vec<Binding> where F: Fn(&Binding) -> bool {
+        let mut values = Vec::new();
+        for (owner, entries) in &self.ephemerons {
+            if !live(owner) { continue; }
+            values.extend(entries.iter()
+                                 .filter(|&(key, _)| live(key))
+                                 .map(|(_, value)| value.clone()));
+        }
+        values
+    }
+
+    /// Drop the weak edges of owners that are no longer allocated, and clear
+    /// those whose target or key is no longer allocated.
+    pub fn clear_dead<F>(&mut self, allocated: F) where F: Fn(&Binding) -> bool {
+        for (owner, targets) in mem::replace(&mut self.refs, HashMap::new()) {
+            if !allocated(&owner) { continue; }
+            let mut live = HashSet::new();
+            for target in targets {
+                if allocated(&target) {
+                    live.insert(target);
+                } else {
+                    self.cleared.push((owner.clone(), target));
+                }
+            }
+            self.refs.insert(owner, live);
+        }
+        for (owner, entries) in mem::replace(&mut self.ephemerons, HashMap::new()) {
+            if !allocated(&owner) { continue; }
+            let mut live = HashMap::new();
+            for (key, value) in entries {
+                if allocated(&key) {
+                    live.insert(key, value);
+                } else {
+                    self.cleared.push((owner.clone(), key));
+                }
+            }
+            self.ephemerons.insert(owner, live);
+        }
+    }
+
+    /// Move the weak edges of `old` to `new`.
+    pub fn rename(&mut self, old: &Binding, new: &Binding) {
+        if let Some(targets) = self.refs.remove(old) {
+            self.refs.insert(new.clone(), targets);
+        }
+        if let Some(entries) = self.ephemerons.remove(old) {
+            self.ephemerons.insert(new.clone(), entries);
+        }
+    }
+
+    /// Point every weak edge at the bindings its ends were moved to.
+    pub fn remap(&mut self, forwarding: &HashMap<Binding, Binding>) {
+        for (owner, targets) in mem::replace(&mut self.refs, HashMap::new()) {
+            let targets = targets.iter().map(|bnd| forwarded(forwarding, bnd)).collect();
+            self.refs.insert(forwarded(forwarding, &owner), targets);
+        }
+        for (owner, entries) in mem::replace(&mut self.ephemerons, HashMap::new()) {
+            let entries = entries.iter()
+                                 .map(|(key, value)| (forwarded(forwarding, key),
+                                                      forwarded(forwarding, value)))
+                                 .collect();
+            self.ephemerons.insert(forwarded(forwarding, &owner), entries);
+        }
+        for &mut (ref mut owner, _) in &mut self.cleared {
+            *owner = forwarded(forwarding, owner);
+        }
+    }
+}
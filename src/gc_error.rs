@@ -8,11 +8,17 @@ use js_types::js_var::{JsPtrEnum, JsVar};
 #[derive(Debug)]
 pub enum GcError {
     Alloc(Binding),
+    ConstAssign(Binding),
     HeapUpdate,
     Load(Binding),
+    NotObject(Binding),
+    OutOfMemory,
     PtrAlloc,
     Scope,
+    Slot(usize, usize),
     Store(JsVar, Option<JsPtrEnum>),
+    Uninitialized(Binding),
+    Unresolved(Binding),
 }
 
 pub type Result<T> = result::Result<T, GcError>;
@@ -21,11 +27,18 @@ impl fmt::Display for GcError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             GcError::Alloc(ref bnd) => write!(f, "Binding {} was already allocated, allocation failed", bnd),
+            GcError::ConstAssign(ref bnd) => write!(f, "Assignment to constant binding {}", bnd),
             GcError::HeapUpdate => write!(f, "Attempted update of invalid heap pointer"),
             GcError::Load(ref bnd) => write!(f, "Lookup of binding {} failed", bnd),
+            GcError::NotObject(ref bnd) => write!(f, "Binding {} is not an object", bnd),
+            GcError::OutOfMemory => write!(f, "Heap limit exceeded, even after collecting garbage"),
             GcError::PtrAlloc => write!(f, "Attempted allocation of bad pointer"),
             GcError::Scope => write!(f, "Parent scope did not exist"),
+            GcError::Slot(depth, index) =>
+                write!(f, "No variable in slot {} of the scope {} levels up", index, depth),
             GcError::Store(ref v, ref p) => write!(f, "Invalid store of var {:?}, ptr {:?}", v, p),
+            GcError::Uninitialized(ref bnd) => write!(f, "Binding {} was accessed before initialization", bnd),
+            GcError::Unresolved(ref bnd) => write!(f, "Assignment to undeclared binding {} in strict mode", bnd),
         }
     }
 }
@@ -34,11 +47,17 @@ impl Error for GcError {
     fn description(&self) -> &str {
         match *self {
             GcError::Alloc(_) => "bad alloc",
+            GcError::ConstAssign(_) => "assignment to const",
             GcError::HeapUpdate => "bad ptr update",
             GcError::Load(_)  => "load of invalid ID",
+            GcError::NotObject(_) => "property access on non-object",
+            GcError::OutOfMemory => "out of memory",
             GcError::PtrAlloc => "bad ptr allocation",
             GcError::Scope    => "no parent scope",
+            GcError::Slot(_,_) => "load of invalid slot",
             GcError::Store(_,_) => "store of invalid ID",
+            GcError::Uninitialized(_) => "access in temporal dead zone",
+            GcError::Unresolved(_) => "unresolved reference",
         }
     }
 }
@@ -0,0 +1,183 @@
+use js_types::js_obj::JsObjStruct;
+use js_types::js_var::{JsKey, JsPtrEnum, JsPtrTag, JsType, JsVar};
+use js_types::binding::Binding;
+
+use alloc::Heap;
+use alloc::policy::{size_of_prop, size_of_ptr};
+use gc_error::{GcError, Result};
+use scope::DeclKind;
+use super::ScopeManager;
+
+/// The local binding the global object itself is known by.
+const GLOBAL_THIS: &'static str = "globalThis";
+
+// Globals are stored as properties of the global object, keyed by their name.
+fn global_key(bnd: &Binding) -> JsKey {
+    JsKey::JsSym(format!("{}", bnd))
+}
+
+impl<H: Heap> ScopeManager<H> {
+    /// The unique binding of the global object, allocating it on first use.
+    /// Global variables are its properties, and it stays rooted for the
+    /// lifetime of the manager.
+    pub fn global_this(&mut self) -> Result<Binding> {
+        if let Some(ref bnd) = self.global_this {
+            return Ok(bnd.clone());
+        }
+        let mut var = JsVar::new(JsType::JsPtr(JsPtrTag::JsObj));
+        var.binding = Binding::new(GLOBAL_THIS.to_owned());
+        let obj = JsPtrEnum::JsObj(JsObjStruct::new(None, "global", Vec::new(),
+                                                    &mut *self.alloc_box.borrow_mut()));
+        try!(self.reserve(1, size_of_ptr(&obj)));
+        try!(self.globals.push_var(var, Some(obj), DeclKind::Var));
+        let unique = match self.globals.get_var_copy(&Binding::new(GLOBAL_THIS.to_owned())) {
+            Some((var, _)) => var.binding,
+            None => return Err(GcError::Scope),
+        };
+        // Collections are rooted at the current scope, whose roots are passed
+        // on to every scope pushed after it and back to its parent on exit.
+        self.curr_scope.add_root(unique.clone());
+        self.global_this = Some(unique.clone());
+        Ok(unique)
+    }
+
+    /// Declare a global variable. Redeclaring an existing global updates it.
+    pub fn declare_global(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        if self.find_global(&var.binding).is_some() {
+            return self.update_global(var, ptr);
+        }
+        try!(self.global_this());
+        let key = global_key(&var.binding);
+        var.binding = Binding::mangle(var.binding.clone());
+        match var.t {
+            JsType::JsPtr(tag) =>
+                if let Some(ptr) = ptr {
+                    if !tag.eq_ptr_type(&ptr) { return Err(GcError::PtrAlloc); }
+                    // The value is reachable through the global object, so it isn't a root
+                    try!(self.reserve(1, size_of_ptr(&ptr) + size_of_prop()));
+                    try!(self.alloc_box.borrow_mut().alloc(var.binding.clone(), ptr));
+                } else {
+                    return Err(GcError::PtrAlloc);
+                },
+            _ => {
+                if let Some(_) = ptr { return Err(GcError::PtrAlloc); }
+                try!(self.reserve(0, size_of_prop()));
+            },
+        }
+        self.set_global_prop(key, Some(var))
+    }
+
+    /// Update a declared global, found either by its name or by the unique
+    /// binding returned when it was loaded. Fails with `GcError::Store` if
+    /// there is no such global. A global that changes type is given a new
+    /// allocation, and its old one is left for the collector.
+    pub fn update_global(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        let (key, old) = match self.find_global(&var.binding) {
+            Some(found) => found,
+            None => return Err(GcError::Store(var, ptr)),
+        };
+        var.binding = old.binding.clone();
+        match var.t {
+            JsType::JsPtr(tag) =>
+                if let Some(ptr) = ptr {
+                    if !tag.eq_ptr_type(&ptr) { return Err(GcError::PtrAlloc); }
+                    let same_type = match old.t {
+                        JsType::JsPtr(old_tag) => old_tag == tag,
+                        _ => false,
+                    };
+                    if same_type {
+                        try!(self.alloc_box.borrow_mut().update_ptr(&var.binding, ptr));
+                    } else {
+                        var.binding = Binding::mangle(old.binding);
+                        try!(self.alloc_box.borrow_mut().alloc(var.binding.clone(), ptr));
+                    }
+                } else {
+                    return Err(GcError::PtrAlloc);
+                },
+            _ => if let Some(_) = ptr { return Err(GcError::PtrAlloc); },
+        }
+        self.set_global_prop(key, Some(var))
+    }
+
+    /// Delete a global. Its value is freed by the next collection unless it is
+    /// still referenced from elsewhere.
+    pub fn delete_global(&mut self, bnd: &Binding) -> Result<()> {
+        match self.find_global(bnd) {
+            Some((key, _)) => self.set_global_prop(key, None),
+            None => Err(GcError::Load(bnd.clone())),
+        }
+    }
+
+    /// Return a copy of a global and its heap value, if it has one.
+    pub fn get_global(&self, bnd: &Binding) -> Option<(JsVar, Option<JsPtrEnum>)> {
+        let var = match self.get_global_var(bnd) {
+            Some(var) => var,
+            None => return None,
+        };
+        if let JsType::JsPtr(_) = var.t {
+            let ptr = match self.alloc_box.borrow().find(&var.binding) {
+                Some(alloc) => alloc.borrow().clone(),
+                None => return None,
+            };
+            Some((var, Some(ptr)))
+        } else {
+            Some((var, None))
+        }
+    }
+
+    // Like `get_global`, but return only the variable. A pointer global is only
+    // found if it has a heap allocation.
+    pub fn get_global_var(&self, bnd: &Binding) -> Option<JsVar> {
+        let var = match self.find_global(bnd) {
+            Some((_, var)) => var,
+            None => return None,
+        };
+        if let JsType::JsPtr(_) = var.t {
+            if self.alloc_box.borrow().find(&var.binding).is_none() {
+                return None;
+            }
+        }
+        Some(var)
+    }
+
+    // Find the property of the global object holding the global named by
+    // `bnd`, or whose value has the unique binding `bnd`.
+    fn find_global(&self, bnd: &Binding) -> Option<(JsKey, JsVar)> {
+        let global = match self.global_this {
+            Some(ref global) => global,
+            None => return None,
+        };
+        let heap = self.alloc_box.borrow();
+        let alloc = match heap.find(global) {
+            Some(alloc) => alloc,
+            None => return None,
+        };
+        let ptr = alloc.borrow();
+        let found = if let JsPtrEnum::JsObj(ref obj) = *ptr {
+            let key = global_key(bnd);
+            match obj.dict.get(&key) {
+                Some(var) => Some((key.clone(), var.clone())),
+                None => obj.dict.iter()
+                                .find(|&(_, var)| var.binding == *bnd)
+                                .map(|(key, var)| (key.clone(), var.clone())),
+            }
+        } else { None };
+        found
+    }
+
+    // Set or remove a property of the global object.
+    fn set_global_prop(&mut self, key: JsKey, var: Option<JsVar>) -> Result<()> {
+        let global = try!(self.global_this());
+        let mut ptr = match self.alloc_box.borrow().find(&global) {
+            Some(alloc) => alloc.borrow().clone(),
+            None => return Err(GcError::HeapUpdate),
+        };
+        if let JsPtrEnum::JsObj(ref mut obj) = ptr {
+            match var {
+                Some(var) => { obj.dict.insert(key, var); },
+                None => { obj.dict.remove(&key); },
+            }
+        }
+        self.alloc_box.borrow_mut().update_ptr(&global, ptr)
+    }
+}
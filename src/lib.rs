@@ -13,35 +13,53 @@ extern crate js_types;
 
 pub mod alloc;
 mod gc_error;
+mod globals;
+mod property;
 mod scope;
 mod test_utils;
+pub mod verify;
 
 use std::cell::RefCell;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, Write};
 use std::mem;
 use std::rc::Rc;
 
 use jsrs_common::ast::Exp;
-use js_types::js_var::{JsPtrEnum, JsVar};
+use js_types::js_var::{JsPtrEnum, JsType, JsVar};
 use js_types::binding::Binding;
 
-use alloc::AllocBox;
-use gc_error::{GcError, Result};
+use alloc::{AllocBox, GcConfig, GcPolicy, GcRef, GcRefMut, GcStats, Heap};
+use alloc::policy::size_of_ptr;
+use gc_error::Result;
 use scope::{Scope, ScopeTag};
+use verify::VerifyReport;
 
-pub struct ScopeManager {
-    globals: Scope,
-    curr_scope: Scope,
-    closures: Vec<Scope>,
-    alloc_box: Rc<RefCell<AllocBox>>,
+pub use gc_error::GcError;
+pub use scope::{DeclKind, Slot};
+
+pub struct ScopeManager<H: Heap = AllocBox> {
+    globals: Scope<H>,
+    curr_scope: Scope<H>,
+    closures: Vec<Scope<H>>,
+    alloc_box: Rc<RefCell<H>>,
+    verify_after_gc: bool,
+    global_this: Option<Binding>,
+    // The heap's sweep count when this manager last caught up with its
+    // collections.
+    swept_at: usize,
 }
 
-impl ScopeManager {
-    fn new(alloc_box: Rc<RefCell<AllocBox>>) -> ScopeManager {
+impl<H: Heap> ScopeManager<H> {
+    fn new(alloc_box: Rc<RefCell<H>>) -> ScopeManager<H> {
         ScopeManager {
             globals: Scope::new(ScopeTag::Call, &alloc_box),
             curr_scope: Scope::new(ScopeTag::Call, &alloc_box),
             closures: Vec::new(),
             alloc_box: alloc_box,
+            verify_after_gc: false,
+            global_this: None,
+            swept_at: 0,
         }
     }
 
@@ -50,46 +68,524 @@ impl ScopeManager {
             Exp::Call(..) => ScopeTag::Call,
             _ => ScopeTag::Block,
         };
-        let parent = mem::replace(&mut self.curr_scope, Scope::new(tag, &self.alloc_box));
+        self.safe_point();
+        let mut scope = Scope::new(tag, &self.alloc_box);
+        if tag == ScopeTag::Call {
+            // The function being called is unknown, so the call is only strict
+            // if the whole program is
+            scope.set_strict(self.globals.is_strict());
+        }
+        let parent = mem::replace(&mut self.curr_scope, scope);
         self.curr_scope.set_parent(parent);
     }
 
+    /// Push the scope of a call to the function bound to `fn_bnd`. If the
+    /// function is a closure, its captured environment becomes the lexical
+    /// parent of the call, so `load` and `store` can resolve its free variables.
+    /// The call is strict if the function was defined in strict mode code,
+    /// regardless of whether its caller is.
+    pub fn push_call(&mut self, fn_bnd: &Binding) {
+        self.safe_point();
+        let strict = self.curr_scope.is_strict_owner(fn_bnd)
+                         .or_else(|| self.closures.iter()
+                                                  .find(|closure| closure.defines(fn_bnd))
+                                                  .map(Scope::is_strict))
+                         .unwrap_or_else(|| self.globals.is_strict());
+        let mut scope = Scope::new(ScopeTag::Call, &self.alloc_box);
+        scope.set_env(fn_bnd.clone());
+        scope.set_strict(strict);
+        let parent = mem::replace(&mut self.curr_scope, scope);
+        self.curr_scope.set_parent(parent);
+    }
+
+    /// Put the current scope, the blocks nested in it, and calls to the
+    /// functions defined in it, into strict mode, as for a `"use strict"`
+    /// directive.
+    pub fn use_strict(&mut self) {
+        self.curr_scope.set_strict(true);
+    }
+
     pub fn pop_scope(&mut self, gc_yield: bool) -> Result<()> {
+        // The heap may have asked for a collection even if the interpreter didn't
+        let gc_yield = gc_yield || self.alloc_box.borrow().gc_requested();
+        let sweeps = self.alloc_box.borrow().sweeps();
         let parent = try!(self.curr_scope.transfer_stack(&mut self.closures, gc_yield));
         if let Some(parent) = parent {
             mem::replace(&mut self.curr_scope, *parent);
+            if self.alloc_box.borrow().sweeps() != sweeps {
+                self.after_gc();
+            } else {
+                self.catch_up();
+            }
             Ok(())
         } else {
             Err(GcError::Scope)
         }
     }
 
-    pub fn alloc(&mut self, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
-        self.curr_scope.push_var(var, ptr)
+    /// The heap backing this manager, e.g. to share it with another manager
+    /// through `ScopeManagerBuilder::alloc_box` or `build_on`.
+    pub fn alloc_box(&self) -> &Rc<RefCell<H>> {
+        &self.alloc_box
+    }
+
+    /// Check the invariants of every scope and of the heap: each local binding
+    /// is on its scope's stack, each pointer on a stack has a heap allocation
+    /// of the matching type, no binding is in two color sets, and no black
+    /// object points to a white one.
+    pub fn verify(&self) -> VerifyReport {
+        let mut report = VerifyReport::new();
+        self.curr_scope.verify_into(&mut report);
+        self.globals.verify_into(&mut report);
+        for closure in self.closures.iter().filter(|closure| closure.is_live_closure()) {
+            closure.verify_into(&mut report);
+        }
+        self.alloc_box.borrow().verify_into(&mut report);
+        report
+    }
+
+    // Catch up with a collection, and in debug builds, optionally verify the
+    // heap after every collection.
+    fn after_gc(&mut self) {
+        self.catch_up();
+        if cfg!(debug_assertions) && self.verify_after_gc {
+            let report = self.verify();
+            assert!(report.is_ok(), "Heap verification failed after collection:\n{}", report);
+        }
+    }
+
+    // If the heap has been swept since this manager last checked, follow the
+    // allocations moved by a compacting collection, and drop the closure
+    // environments whose functions were collected. Collections run outside of
+    // this manager, e.g. by another manager sharing the heap, are caught up
+    // with here; until then, `closure_chain` skips dead closures.
+    fn catch_up(&mut self) {
+        let sweeps = self.alloc_box.borrow().sweeps();
+        if sweeps != self.swept_at {
+            self.remap_moved();
+            self.closures.retain(|closure| closure.is_live_closure());
+            self.swept_at = sweeps;
+        }
+    }
+
+    // Point every scope, closure environment and the global object at the
+    // bindings the last compacting collection moved their allocations to.
+    fn remap_moved(&mut self) {
+        let alloc_box = self.alloc_box.clone();
+        let heap = alloc_box.borrow();
+        let forwarding = match heap.forwarding() {
+            Some(forwarding) if !forwarding.is_empty() => forwarding,
+            _ => return,
+        };
+        self.curr_scope.remap(forwarding);
+        self.globals.remap(forwarding);
+        for closure in &mut self.closures {
+            closure.remap(forwarding);
+        }
+        if let Some(ref mut global_this) = self.global_this {
+            *global_this = alloc::forwarded(forwarding, global_this);
+        }
+    }
+
+    // The binding the allocation at `bnd` was moved to by the last compacting
+    // collection, so that a variable loaded before it can still be stored to.
+    fn forwarded(&self, bnd: &Binding) -> Binding {
+        match self.alloc_box.borrow().forwarding() {
+            Some(forwarding) => alloc::forwarded(forwarding, bnd),
+            None => bnd.clone(),
+        }
+    }
+
+    /// Write a `.heapsnapshot` of the heap that can be loaded into Chrome
+    /// DevTools. The roots of the current scope chain, the globals, and the
+    /// bindings captured by live closures are shown under their local names.
+    pub fn write_heap_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let captured = self.closures.iter()
+                                    .filter(|closure| closure.is_live_closure())
+                                    .flat_map(|closure| closure.named_roots());
+        let roots: Vec<_> = self.curr_scope.named_roots().into_iter()
+                                .chain(self.globals.named_roots())
+                                .chain(captured)
+                                .map(|(local, unique)| (format!("{}", local), unique))
+                                .collect();
+        self.alloc_box.borrow().write_heap_snapshot(&roots, out)
+    }
+
+    /// Render the scope chain, the closure scopes, the globals and the heap as
+    /// a Graphviz DOT graph. Heap objects are filled with their current color
+    /// in the collector, and roots are drawn with a double border.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        self.write_dot(&mut out).expect("writing to a String cannot fail");
+        out
+    }
+
+    fn write_dot(&self, out: &mut String) -> fmt::Result {
+        try!(writeln!(out, "digraph gc {{"));
+        try!(self.curr_scope.write_dot("scope", out));
+        try!(self.globals.write_dot("globals", out));
+        for (i, closure) in self.closures.iter().filter(|closure| closure.is_live_closure()).enumerate() {
+            try!(closure.write_dot(&format!("closure{}", i), out));
+        }
+        try!(self.alloc_box.borrow().write_dot(out));
+        writeln!(out, "}}")
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.alloc_box.borrow().stats()
+    }
+
+    pub fn reset_gc_stats(&mut self) {
+        self.alloc_box.borrow_mut().reset_stats();
+    }
+
+    /// Run a collection if the heap's `GcPolicy` has requested one. Called on
+    /// every scope push and pop; interpreters may also call it from any other
+    /// point at which no heap pointers are held outside of a scope. Returns
+    /// whether a collection ran.
+    pub fn safe_point(&mut self) -> bool {
+        if self.alloc_box.borrow().gc_requested() {
+            self.curr_scope.collect(false);
+            self.after_gc();
+            true
+        } else {
+            self.catch_up();
+            false
+        }
+    }
+
+    /// Begin an incremental collection rooted at the current scope. The cycle
+    /// is advanced with `gc_step` and completed with `finish_gc`, so marking
+    /// work can be spread across the interpreter's event loop.
+    pub fn start_gc(&mut self) {
+        self.alloc_box.borrow_mut().start_cycle(&self.curr_scope.roots());
+    }
+
+    /// Perform at most `budget` units of marking work. Returns true once
+    /// marking is complete and the cycle is ready for `finish_gc`.
+    pub fn gc_step(&mut self, budget: usize) -> bool {
+        self.alloc_box.borrow_mut().mark_step(budget)
+    }
+
+    /// Complete the current incremental cycle, sweeping unreachable objects.
+    pub fn finish_gc(&mut self) {
+        self.alloc_box.borrow_mut().finish_cycle();
+        // Follow any allocations the cycle moved before looking for dead roots
+        self.catch_up();
+        self.curr_scope.pop_dead_roots();
+        self.after_gc();
+    }
+
+    pub fn alloc(&mut self, var: JsVar, ptr: Option<JsPtrEnum>, kind: DeclKind) -> Result<()> {
+        if let Some(ref ptr) = ptr {
+            try!(self.reserve(1, size_of_ptr(ptr)));
+        }
+        self.curr_scope.push_var(var, ptr, kind)
+    }
+
+    /// Hoist a declaration to the top of the scope that owns it, ahead of its
+    /// initialisation with `alloc`. Until then, loading a `let` or `const`
+    /// binding fails with `GcError::Uninitialized`.
+    pub fn declare(&mut self, bnd: Binding, kind: DeclKind) {
+        self.curr_scope.hoist(bnd, kind);
+    }
+
+    /// Resolve a binding to a slot in the current scope chain, so that repeated
+    /// accesses can use `load_slot` and `store_slot` instead of searching by
+    /// name. Returns `None` for bindings that can only be found dynamically by
+    /// `load` and `store`, such as captured variables and globals.
+    pub fn resolve(&self, bnd: &Binding) -> Option<Slot> {
+        self.curr_scope.resolve(bnd)
+    }
+
+    pub fn load_slot(&self, slot: Slot) -> Result<(JsVar, Option<JsPtrEnum>)> {
+        self.curr_scope.load_slot(slot)
+    }
+
+    pub fn store_slot(&mut self, slot: Slot, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        let bnd = match self.curr_scope.slot_binding(slot) {
+            Some(bnd) => bnd.clone(),
+            None => return Err(GcError::Slot(slot.depth, slot.index)),
+        };
+        let mut slot_var = var.clone();
+        slot_var.binding = bnd;
+        try!(self.reserve_store(&slot_var, &ptr));
+        self.curr_scope.store_slot(slot, var, ptr)
+    }
+
+    // Make sure the heap has room to store `ptr` into the variable `var`. A
+    // value of the same type as the current one replaces it in place, while
+    // any other needs a new allocation.
+    fn reserve_store(&mut self, var: &JsVar, ptr: &Option<JsPtrEnum>) -> Result<()> {
+        if let Some(ref ptr) = *ptr {
+            let old_size = match var.t {
+                JsType::JsPtr(tag) => match self.alloc_box.borrow().find(&var.binding) {
+                    Some(alloc) if tag.eq_ptr_type(&*alloc.borrow()) => Some(size_of_ptr(&*alloc.borrow())),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let new_size = size_of_ptr(ptr);
+            match old_size {
+                Some(old_size) => self.reserve(0, new_size.saturating_sub(old_size)),
+                None => self.reserve(1, new_size),
+            }
+        } else { Ok(()) }
+    }
+
+    /// Make sure the heap has room for `objects` more allocations totalling
+    /// `bytes`. If it doesn't, run an emergency full collection, and if there's
+    /// still no room, fail with `GcError::OutOfMemory` so the embedder can
+    /// throw instead of letting the heap grow without bound.
+    fn reserve(&mut self, objects: usize, bytes: usize) -> Result<()> {
+        if self.alloc_box.borrow().has_room(objects, bytes) {
+            return Ok(());
+        }
+        self.curr_scope.collect(true);
+        self.after_gc();
+        if self.alloc_box.borrow().has_room(objects, bytes) {
+            Ok(())
+        } else {
+            Err(GcError::OutOfMemory)
+        }
     }
 
     /// Try to load the variable behind a binding
     pub fn load(&self, bnd: &Binding) -> Result<(JsVar, Option<JsPtrEnum>)> {
-        self.curr_scope.get_var_copy(bnd)
-                       // Binding lookup failed locally, so check the root
-                       // scope (globals)
-                       .or(self.globals.get_var_copy(bnd))
-                       .ok_or_else(|| GcError::Load(bnd.clone()))
+        let var = try!(self.find_var(bnd));
+        if let JsType::JsPtr(_) = var.t {
+            let ptr = match self.alloc_box.borrow().find(&var.binding) {
+                Some(alloc) => alloc.borrow().clone(),
+                None => return Err(GcError::Load(bnd.clone())),
+            };
+            Ok((var, Some(ptr)))
+        } else {
+            Ok((var, None))
+        }
     }
 
-    pub fn store(&mut self, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
-        let update = self.curr_scope.update_var(var, ptr);
+    /// Like `load`, but borrow the variable's heap value in place through a
+    /// handle, instead of copying it out of the heap. The manager can't be
+    /// updated while the handle is held. If the heap is shared with other
+    /// managers, allocating, storing or collecting through any of them while
+    /// it is held panics; see `GcRef`.
+    pub fn load_ref(&self, bnd: &Binding) -> Result<(JsVar, Option<GcRef<H>>)> {
+        let var = try!(self.find_var(bnd));
+        let handle = match var.t {
+            JsType::JsPtr(_) => Some(GcRef::new(&self.alloc_box, var.binding.clone())),
+            _ => None,
+        };
+        Ok((var, handle))
+    }
+
+    /// Like `load_ref`, but the handle allows the heap value to be mutated in
+    /// place, rather than by a `store` of a modified copy. The handle borrows
+    /// the manager mutably, so no collection can run until it's dropped and
+    /// the write barrier has seen the mutation. If the heap is shared with
+    /// other managers, allocating, storing or collecting through any of them
+    /// while it is held panics, as with `load_ref`.
+    pub fn load_mut(&mut self, bnd: &Binding) -> Result<(JsVar, Option<GcRefMut<H>>)> {
+        let var = try!(self.find_var(bnd));
+        let handle = match var.t {
+            JsType::JsPtr(_) => Some(GcRefMut::new(&self.alloc_box, var.binding.clone())),
+            _ => None,
+        };
+        Ok((var, handle))
+    }
+
+    // Find a variable. A pointer variable is only found if it has a heap
+    // allocation.
+    fn find_var(&self, bnd: &Binding) -> Result<JsVar> {
+        if let Some(found) = self.curr_scope.get_var(bnd) {
+            if self.curr_scope.in_tdz(&found.binding) {
+                return Err(GcError::Uninitialized(bnd.clone()));
+            }
+            return Ok(found);
+        }
+        // Binding lookup failed locally, so check the environments captured by
+        // the closure being called, innermost first
+        for i in self.closure_chain() {
+            if let Some(found) = self.closures[i].get_var(bnd) {
+                if self.closures[i].in_tdz(&found.binding) {
+                    return Err(GcError::Uninitialized(bnd.clone()));
+                }
+                return Ok(found);
+            }
+        }
+        // Finally, check the root scope (globals), and the global object
+        self.globals.get_var(bnd)
+                    .or_else(|| self.get_global_var(bnd))
+                    .ok_or_else(|| GcError::Load(bnd.clone()))
+    }
+
+    // The indices in `closures` of the captured environments visible from the
+    // current scope, innermost first.
+    fn closure_chain(&self) -> Vec<usize> {
+        let mut chain = Vec::new();
+        let mut env = self.curr_scope.env().cloned();
+        while let Some(fn_bnd) = env {
+            // A closure may have died in a collection that it hasn't been pruned after yet
+            match self.closures.iter().position(|closure| closure.defines(&fn_bnd)) {
+                Some(i) if !chain.contains(&i) && self.closures[i].is_live_closure() => {
+                    env = self.closures[i].env().cloned();
+                    chain.push(i);
+                }
+                _ => break,
+            }
+        }
+        chain
+    }
+
+    pub fn store(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        // The variable may have been loaded before a compacting collection, and
+        // making room for the store may run another
+        var.binding = self.forwarded(&var.binding);
+        try!(self.reserve_store(&var, &ptr));
+        var.binding = self.forwarded(&var.binding);
+        let mut update = self.curr_scope.update_var(var, ptr);
+        for i in self.closure_chain() {
+            update = match update {
+                Err(GcError::Store(var, ptr)) => self.closures[i].update_var(var, ptr),
+                _ => break,
+            };
+        }
         if let Err(GcError::Store(var, ptr)) = update {
-            self.alloc(var, ptr)
+            // Assigning to an undeclared name creates a global, unless this is
+            // strict mode code, where it's a ReferenceError
+            match self.update_global(var, ptr) {
+                Err(GcError::Store(var, ptr)) =>
+                    if self.curr_scope.is_strict() {
+                        Err(GcError::Unresolved(var.binding))
+                    } else {
+                        self.declare_global(var, ptr)
+                    },
+                res => res,
+            }
         } else {
             update
         }
     }
 }
 
+/// Builds a `ScopeManager` with a custom heap configuration, or on top of an
+/// existing heap shared with other managers. A heap passed in with `alloc_box`
+/// or `build_on` keeps its current configuration unless one of the other
+/// options is set, in which case the resulting `GcConfig` is applied to it.
+#[derive(Default)]
+pub struct ScopeManagerBuilder {
+    alloc_box: Option<Rc<RefCell<AllocBox>>>,
+    config: Option<GcConfig>,
+    verify: bool,
+    strict: bool,
+}
+
+impl ScopeManagerBuilder {
+    pub fn new() -> ScopeManagerBuilder {
+        ScopeManagerBuilder::default()
+    }
+
+    /// Share an existing heap instead of creating a new one. Every manager's
+    /// scopes register their roots with the heap, so a collection run by any
+    /// of them keeps alive whatever the others can still reach.
+    pub fn alloc_box(mut self, alloc_box: Rc<RefCell<AllocBox>>) -> ScopeManagerBuilder {
+        self.alloc_box = Some(alloc_box);
+        self
+    }
+
+    /// Replace the whole heap configuration.
+    pub fn config(mut self, config: GcConfig) -> ScopeManagerBuilder {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn policy(mut self, policy: GcPolicy) -> ScopeManagerBuilder {
+        self.config_mut().policy = policy;
+        self
+    }
+
+    pub fn heap_limit(mut self, max_objects: Option<usize>, max_bytes: Option<usize>) -> ScopeManagerBuilder {
+        {
+            let policy = &mut self.config_mut().policy;
+            policy.max_objects = max_objects;
+            policy.max_bytes = max_bytes;
+        }
+        self
+    }
+
+    pub fn nursery(mut self, promote_age: usize, major_interval: usize) -> ScopeManagerBuilder {
+        self.config_mut().nursery = Some((promote_age, major_interval));
+        self
+    }
+
+    pub fn trace(mut self, trace: bool) -> ScopeManagerBuilder {
+        self.config_mut().trace = trace;
+        self
+    }
+
+    pub fn stats(mut self, stats: bool) -> ScopeManagerBuilder {
+        self.config_mut().stats = stats;
+        self
+    }
+
+    /// Compact the heap after every full or incremental collection. The
+    /// survivors move to new bindings, which the manager follows in its
+    /// scopes, so a variable returned by `load` before a collection can still
+    /// be passed to `store` after it, though not after a second one. Managers
+    /// sharing the heap follow a collection at their next scope push or pop,
+    /// `safe_point` or allocation, and must do so before the heap compacts
+    /// again.
+    pub fn compact(mut self, compact: bool) -> ScopeManagerBuilder {
+        self.config_mut().compact = compact;
+        self
+    }
+
+    /// In debug builds, run `ScopeManager::verify` after every collection and
+    /// panic with its report if any invariant is broken.
+    pub fn verify(mut self, verify: bool) -> ScopeManagerBuilder {
+        self.verify = verify;
+        self
+    }
+
+    /// Run all code in strict mode, as if the program began with `"use strict"`.
+    pub fn strict(mut self, strict: bool) -> ScopeManagerBuilder {
+        self.strict = strict;
+        self
+    }
+
+    pub fn build(mut self) -> ScopeManager {
+        let alloc_box = self.alloc_box.take().unwrap_or_else(|| Rc::new(RefCell::new(AllocBox::new())));
+        self.build_on(alloc_box)
+    }
+
+    /// Build a manager on any heap storage engine, such as a `SlabAllocBox`,
+    /// instead of an `AllocBox`. The heap set with `alloc_box`, if any, is
+    /// ignored.
+    pub fn build_on<H: Heap>(self, heap: Rc<RefCell<H>>) -> ScopeManager<H> {
+        if let Some(config) = self.config {
+            heap.borrow_mut().configure(config);
+        }
+        let mut mgr = ScopeManager::new(heap);
+        mgr.verify_after_gc = self.verify;
+        if self.strict {
+            mgr.globals.set_strict(true);
+            mgr.use_strict();
+        }
+        mgr
+    }
+
+    fn config_mut(&mut self) -> &mut GcConfig {
+        if self.config.is_none() {
+            self.config = Some(GcConfig::default());
+        }
+        match self.config {
+            Some(ref mut config) => config,
+            None => unreachable!(),
+        }
+    }
+}
+
 pub fn init_gc() -> ScopeManager {
-    let alloc_box = Rc::new(RefCell::new(AllocBox::new()));
-    ScopeManager::new(alloc_box)
+    ScopeManagerBuilder::new().build()
 }
 
 
@@ -97,10 +593,16 @@ pub fn init_gc() -> ScopeManager {
 mod tests {
     use super::*;
 
-    use jsrs_common::ast::Exp;
-    use js_types::js_var::JsType;
+    use std::cell::RefCell;
+    use std::collections::hash_set::HashSet;
+    use std::rc::Rc;
+
+    use jsrs_common::ast::{Exp, Stmt};
+    use js_types::allocator::Allocator;
+    use js_types::js_var::{JsKey, JsPtrEnum, JsPtrTag, JsType};
     use js_types::binding::Binding;
 
+    use alloc::{GcPolicy, SlabAllocBox};
     use gc_error::GcError;
     use test_utils;
 
@@ -127,9 +629,9 @@ mod tests {
     fn test_alloc() {
         let alloc_box = test_utils::make_alloc_box();
         let mut mgr = ScopeManager::new(alloc_box);
-        mgr.alloc(test_utils::make_num(1.), None).unwrap();
+        mgr.alloc(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
         mgr.push_scope(&Exp::Undefined);
-        mgr.alloc(test_utils::make_num(2.), None).unwrap();
+        mgr.alloc(test_utils::make_num(2.), None, DeclKind::Let).unwrap();
         assert!(mgr.alloc_box.borrow().is_empty());
     }
 
@@ -139,7 +641,7 @@ mod tests {
         let mut mgr = ScopeManager::new(alloc_box);
         let x = test_utils::make_num(1.);
         let x_bnd = x.binding.clone();
-        mgr.alloc(x, None).unwrap();
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
         let load = mgr.load(&x_bnd);
         assert!(load.is_ok());
         let load = load.unwrap();
@@ -170,7 +672,7 @@ mod tests {
         mgr.push_scope(&Exp::Undefined);
         let x = test_utils::make_num(1.);
         let x_bnd = x.binding.clone();
-        mgr.alloc(x, None).unwrap();
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
 
         let (mut var, _) = mgr.load(&x_bnd).unwrap();
         var.t = JsType::JsNum(2.);
@@ -194,4 +696,821 @@ mod tests {
         assert!(ptr.is_none());
     }
 
+    #[test]
+    fn test_incremental_gc() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+
+        mgr.start_gc();
+        // Allocations made mid-cycle survive the sweep that ends it
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        mgr.alloc(y, Some(y_ptr), DeclKind::Let).unwrap();
+        while !mgr.gc_step(1) {}
+        mgr.finish_gc();
+
+        assert_eq!(mgr.alloc_box.borrow().len(), 2);
+        assert!(mgr.load(&x_bnd).is_ok());
+        assert!(mgr.load(&y_bnd).is_ok());
+    }
+
+    #[test]
+    fn test_incremental_gc_store() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+
+        mgr.start_gc();
+        let (var, _) = mgr.load(&x_bnd).unwrap();
+        let (_, new_ptr, _) = test_utils::make_str("updated");
+        mgr.store(var, Some(new_ptr)).unwrap();
+        mgr.finish_gc();
+
+        match mgr.load(&x_bnd).unwrap().1 {
+            Some(JsPtrEnum::JsStr(ref s)) => assert_eq!(s.text, "updated"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_policy_triggered_gc() {
+        let alloc_box = test_utils::make_alloc_box();
+        alloc_box.borrow_mut().set_policy(GcPolicy {
+            object_threshold: 1,
+            byte_threshold: usize::max_value(),
+            growth_factor: 1.0,
+            min_interval: 1,
+            ..GcPolicy::default()
+        });
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        assert!(!mgr.safe_point());
+
+        // Orphan a heap object so there is something to collect
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        mgr.alloc_box.borrow_mut().alloc(Binding::anon(), y_ptr).unwrap();
+        assert!(mgr.alloc_box.borrow().gc_requested());
+
+        // Pushing a scope is a safe point
+        mgr.push_scope(&Exp::Undefined);
+        assert!(!mgr.alloc_box.borrow().gc_requested());
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+        assert!(mgr.load(&x_bnd).is_ok());
+    }
+
+    #[test]
+    fn test_alloc_out_of_memory() {
+        let alloc_box = test_utils::make_alloc_box();
+        alloc_box.borrow_mut().set_policy(GcPolicy {
+            max_objects: Some(1),
+            ..GcPolicy::default()
+        });
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (y, y_ptr, _) = test_utils::make_str("y");
+        let res = mgr.alloc(y, Some(y_ptr), DeclKind::Let);
+        assert!(matches!(res, Err(GcError::OutOfMemory)));
+        // Primitives don't touch the heap, so they can still be allocated
+        assert!(mgr.alloc(test_utils::make_num(1.), None, DeclKind::Let).is_ok());
+    }
+
+    #[test]
+    fn test_alloc_emergency_collection() {
+        let alloc_box = test_utils::make_alloc_box();
+        alloc_box.borrow_mut().set_policy(GcPolicy {
+            max_objects: Some(1),
+            ..GcPolicy::default()
+        });
+        let mut mgr = ScopeManager::new(alloc_box);
+        // Fill the heap with garbage
+        let (_, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc_box.borrow_mut().alloc(Binding::anon(), x_ptr).unwrap();
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        assert!(mgr.alloc(y, Some(y_ptr), DeclKind::Let).is_ok());
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+        assert!(mgr.load(&y_bnd).is_ok());
+    }
+
+    #[test]
+    fn test_store_out_of_memory() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let max_bytes = mgr.alloc_box.borrow().byte_len();
+        mgr.alloc_box.borrow_mut().set_policy(GcPolicy {
+            max_bytes: Some(max_bytes),
+            ..GcPolicy::default()
+        });
+
+        let (var, _) = mgr.load(&x_bnd).unwrap();
+        let (_, big_ptr, _) = test_utils::make_str("a string that no longer fits in the heap");
+        let res = mgr.store(var.clone(), Some(big_ptr));
+        assert!(matches!(res, Err(GcError::OutOfMemory)));
+        // Same-sized updates still fit
+        let (_, same_ptr, _) = test_utils::make_str("y");
+        assert!(mgr.store(var, Some(same_ptr)).is_ok());
+    }
+
+    #[test]
+    fn test_builder_default() {
+        let mgr = ScopeManagerBuilder::new().build();
+        assert!(mgr.alloc_box.borrow().is_empty());
+        assert!(mgr.alloc_box.borrow().policy().max_objects.is_none());
+    }
+
+    #[test]
+    fn test_builder_config() {
+        let mut mgr = ScopeManagerBuilder::new().heap_limit(Some(1), None)
+                                                .nursery(2, 4)
+                                                .build();
+        assert_eq!(mgr.alloc_box.borrow().policy().max_objects, Some(1));
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().young_len(), 1);
+        let (y, y_ptr, _) = test_utils::make_str("y");
+        assert!(matches!(mgr.alloc(y, Some(y_ptr), DeclKind::Let), Err(GcError::OutOfMemory)));
+    }
+
+    #[test]
+    fn test_builder_shared_alloc_box() {
+        let first = init_gc();
+        let mut second = ScopeManagerBuilder::new().alloc_box(first.alloc_box().clone()).build();
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        second.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        assert_eq!(first.alloc_box.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_shared_alloc_box_roots() {
+        let mut first = init_gc();
+        let mut second = ScopeManagerBuilder::new().alloc_box(first.alloc_box().clone()).build();
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        second.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+
+        // Collections run by one manager respect the roots of the other
+        first.curr_scope.collect(true);
+        assert!(matches!(second.load(&x_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+        first.start_gc();
+        first.finish_gc();
+        assert!(matches!(second.load(&x_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+
+        // Until that manager goes away
+        drop(second);
+        first.curr_scope.collect(true);
+        assert!(first.alloc_box.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_build_on_slab() {
+        let heap = Rc::new(RefCell::new(SlabAllocBox::new()));
+        let mut mgr = ScopeManagerBuilder::new().heap_limit(Some(2), None).build_on(heap.clone());
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.push_scope(&Exp::Undefined);
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        mgr.alloc(y, Some(y_ptr), DeclKind::Let).unwrap();
+        mgr.pop_scope(false).unwrap();
+
+        // Popping without a collection moves the block's string into the parent
+        // scope, so making room for another string collects nothing
+        let (z, z_ptr, _) = test_utils::make_str("z");
+        assert!(matches!(mgr.alloc(z, Some(z_ptr), DeclKind::Let), Err(GcError::OutOfMemory)));
+        assert_eq!(heap.borrow().len(), 2);
+        assert_eq!(heap.borrow().sweeps(), 1);
+        assert!(matches!(mgr.load(&x_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+        assert!(matches!(mgr.load(&y_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+        assert!(mgr.verify().is_ok());
+    }
+
+    #[test]
+    fn test_gc_stats() {
+        let mut mgr = ScopeManagerBuilder::new().stats(true).build();
+        mgr.push_scope(&Exp::Undefined);
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.pop_scope(true).unwrap();
+
+        let stats = mgr.gc_stats();
+        assert_eq!(stats.collections, 1);
+        assert_eq!(stats.cycles.len(), 1);
+        assert_eq!(stats.live.strs, 1);
+        assert_eq!(stats.peak_objects, 1);
+
+        mgr.reset_gc_stats();
+        assert_eq!(mgr.gc_stats().collections, 0);
+    }
+
+    #[test]
+    fn test_write_heap_snapshot() {
+        let mut mgr = init_gc();
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let mut out = Vec::new();
+        mgr.write_heap_snapshot(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"node_count\":2,"));
+        assert!(json.contains("\"edge_count\":1,"));
+    }
+
+    #[test]
+    fn test_write_heap_snapshot_closure_roots() {
+        let mut mgr = init_gc();
+        mgr.push_scope(&Exp::Undefined);
+        let body = Stmt::Ret(Exp::Var("x".to_owned()));
+        let (f, f_ptr, _) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+        let (x, x_ptr, _) = test_utils::make_named_str("x", "captured");
+        mgr.alloc(f, Some(f_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.pop_scope(false).unwrap();
+
+        // The function is a root of the current scope, and the closure's
+        // bindings are roots too, besides the function's edge to the string
+        let mut out = Vec::new();
+        mgr.write_heap_snapshot(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"node_count\":3,"));
+        assert!(json.contains("\"edge_count\":4,"));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut mgr = init_gc();
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.push_scope(&Exp::Undefined);
+        mgr.alloc(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+
+        let dot = mgr.to_dot();
+        assert!(dot.starts_with("digraph gc {"));
+        assert!(dot.trim_right().ends_with("}"));
+        assert!(dot.contains("\"scope\" -> \"scope^\" [style=dashed, label=parent];"));
+        assert!(dot.contains("peripheries=2"));
+        assert!(dot.contains("fillcolor=white"));
+    }
+
+    #[test]
+    fn test_to_dot_closure_roots() {
+        let mut mgr = init_gc();
+        mgr.push_scope(&Exp::Undefined);
+        let body = Stmt::Ret(Exp::Var("x".to_owned()));
+        let (f, f_ptr, _) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+        let (x, x_ptr, _) = test_utils::make_named_str("x", "captured");
+        mgr.alloc(f, Some(f_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.pop_scope(false).unwrap();
+
+        // The bindings a closure keeps alive are drawn as its roots
+        let dot = mgr.to_dot();
+        assert!(dot.contains("label=\"closure0 (Call, 2 roots)\""));
+        assert!(dot.contains("\"closure0:x\" [shape=record, peripheries=2,"));
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut mgr = ScopeManagerBuilder::new().verify(true).build();
+        mgr.push_scope(&Exp::Undefined);
+        let (x, x_ptr, _) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+        assert!(mgr.verify().is_ok());
+        // Collecting runs the verifier, which would panic on a violation
+        mgr.pop_scope(true).unwrap();
+        assert!(mgr.verify().is_ok());
+    }
+
+    #[test]
+    fn test_load_store_closure_env() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        let body = Stmt::Ret(Exp::Var("x".to_owned()));
+        let (f, f_ptr, f_bnd) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+        let mut x = test_utils::make_num(1.);
+        x.binding = Binding::new("x".to_owned());
+        let x_bnd = x.binding.clone();
+        mgr.alloc(f, Some(f_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
+        let fn_unique = mgr.load(&f_bnd).unwrap().0.binding;
+        mgr.pop_scope(false).unwrap();
+        // Outside of a call to the closure, its environment is invisible
+        assert!(mgr.load(&x_bnd).is_err());
+
+        mgr.push_call(&fn_unique);
+        mgr.push_scope(&Exp::Undefined);
+        let (mut x, _) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(x.t, JsType::JsNum(n) if n == 1.));
+        x.t = JsType::JsNum(2.);
+        mgr.store(x, None).unwrap();
+        mgr.pop_scope(false).unwrap();
+        mgr.pop_scope(false).unwrap();
+
+        // The store went to the captured variable, not a new local
+        mgr.push_call(&fn_unique);
+        let (mut x, _) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(x.t, JsType::JsNum(n) if n == 2.));
+
+        // A captured primitive that becomes a pointer lives as long as the closure
+        let (_, s_ptr, _) = test_utils::make_str("s");
+        x.t = JsType::JsPtr(JsPtrTag::JsStr);
+        mgr.store(x, Some(s_ptr)).unwrap();
+        mgr.curr_scope.collect(true);
+        assert!(matches!(mgr.load(&x_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+    }
+
+    #[test]
+    fn test_prune_closures_after_any_collection() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        let body = Stmt::Ret(Exp::Var("x".to_owned()));
+        let (f, f_ptr, f_bnd) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+        let (x, x_ptr, x_bnd) = test_utils::make_named_str("x", "captured");
+        mgr.alloc(f, Some(f_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let fn_unique = mgr.load(&f_bnd).unwrap().0.binding;
+        mgr.pop_scope(false).unwrap();
+
+        // The closure dies behind the manager's back, so it is skipped until
+        // the manager notices
+        mgr.alloc_box.borrow_mut().free(&fn_unique);
+        mgr.push_call(&fn_unique);
+        assert!(mgr.load(&x_bnd).is_err());
+        mgr.pop_scope(false).unwrap();
+
+        // A collection run by anything but the manager frees the environment,
+        // and the closure is pruned at the next scope change
+        mgr.alloc_box.borrow_mut().collect(&HashSet::new());
+        assert!(mgr.alloc_box.borrow().is_empty());
+        assert!(mgr.verify().is_ok());
+        mgr.push_scope(&Exp::Undefined);
+        assert!(mgr.closures.is_empty());
+    }
+
+    #[test]
+    fn test_declare_global() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.declare_global(x, Some(x_ptr)).unwrap();
+        // The global object and its property
+        assert_eq!(mgr.alloc_box.borrow().len(), 2);
+
+        // Globals are visible from any scope, and survive collections
+        mgr.push_scope(&Exp::Undefined);
+        mgr.curr_scope.collect(true);
+        let (var, ptr) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(ptr, Some(JsPtrEnum::JsStr(_))));
+
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        mgr.update_global(var, Some(y_ptr)).unwrap();
+        match mgr.load(&x_bnd).unwrap() {
+            (_, Some(JsPtrEnum::JsStr(ref s))) => assert_eq!(s.text, "y"),
+            _ => unreachable!(),
+        }
+        mgr.pop_scope(false).unwrap();
+
+        mgr.delete_global(&x_bnd).unwrap();
+        assert!(mgr.load(&x_bnd).is_err());
+        mgr.curr_scope.collect(true);
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_update_global_retype() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.declare_global(x, None).unwrap();
+
+        // Primitive to pointer
+        let (mut var, _) = mgr.load(&x_bnd).unwrap();
+        let (_, s_ptr, _) = test_utils::make_str("s");
+        var.t = JsType::JsPtr(JsPtrTag::JsStr);
+        mgr.store(var, Some(s_ptr)).unwrap();
+        let (var, ptr) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(ptr, Some(JsPtrEnum::JsStr(_))));
+        let s_unique = var.binding.clone();
+
+        // Pointer to another pointer type
+        let (_, obj_ptr, _) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        let mut var = var;
+        var.t = JsType::JsPtr(JsPtrTag::JsObj);
+        mgr.store(var, Some(obj_ptr)).unwrap();
+        let (var, ptr) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(ptr, Some(JsPtrEnum::JsObj(_))));
+        assert!(var.binding != s_unique);
+
+        // Pointer to primitive
+        let mut var = var;
+        var.t = JsType::JsNum(2.);
+        mgr.store(var, None).unwrap();
+        let (var, ptr) = mgr.load(&x_bnd).unwrap();
+        assert!(matches!(var.t, JsType::JsNum(n) if n == 2.));
+        assert!(ptr.is_none());
+        assert!(mgr.verify().is_ok());
+
+        // The old values were left for the collector
+        assert_eq!(mgr.alloc_box.borrow().len(), 3);
+        mgr.curr_scope.collect(true);
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_global_this() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.declare_global(x, None).unwrap();
+
+        let global = mgr.global_this().unwrap();
+        assert_eq!(mgr.global_this().unwrap(), global);
+        // The global object can be loaded like any other variable, and holds
+        // every global as a property
+        let (var, ptr) = mgr.load(&Binding::new("globalThis".to_owned())).unwrap();
+        assert_eq!(var.binding, global);
+        match ptr {
+            Some(JsPtrEnum::JsObj(ref obj)) => assert_eq!(obj.dict.len(), 1),
+            _ => unreachable!(),
+        }
+        assert!(mgr.get_global(&x_bnd).is_some());
+    }
+
+    #[test]
+    fn test_store_creates_global() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.store(x, None).unwrap();
+        mgr.pop_scope(false).unwrap();
+        // The assignment outlived the scope it was made in
+        assert!(mgr.get_global(&x_bnd).is_some());
+        assert!(mgr.load(&x_bnd).is_ok());
+    }
+
+    #[test]
+    fn test_alloc_var_hoisted_to_call() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        let y = test_utils::make_num(1.);
+        let y_bnd = y.binding.clone();
+        mgr.alloc(x, Some(x_ptr), DeclKind::Var).unwrap();
+        mgr.alloc(y, None, DeclKind::Let).unwrap();
+        mgr.pop_scope(true).unwrap();
+        // The var outlives the block it was declared in, but the let doesn't
+        assert!(mgr.load(&x_bnd).is_ok());
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+        assert!(mgr.load(&y_bnd).is_err());
+    }
+
+    #[test]
+    fn test_load_tdz() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.declare(x_bnd.clone(), DeclKind::Let);
+        assert!(matches!(mgr.load(&x_bnd), Err(GcError::Uninitialized(_))));
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
+        assert!(mgr.load(&x_bnd).is_ok());
+
+        // A hoisted var is undefined rather than uninitialised
+        let y = test_utils::make_num(1.);
+        mgr.declare(y.binding.clone(), DeclKind::Var);
+        let (var, _) = mgr.load(&y.binding).unwrap();
+        assert!(matches!(var.t, JsType::JsUndef));
+    }
+
+    #[test]
+    fn test_store_const() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.alloc(x, None, DeclKind::Const).unwrap();
+        let (mut var, _) = mgr.load(&x_bnd).unwrap();
+        var.t = JsType::JsNum(2.);
+        assert!(matches!(mgr.store(var, None), Err(GcError::ConstAssign(_))));
+    }
+
+    #[test]
+    fn test_store_strict() {
+        let mut mgr = ScopeManagerBuilder::new().strict(true).build();
+        mgr.push_scope(&Exp::Undefined);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        assert!(matches!(mgr.store(x, None), Err(GcError::Unresolved(_))));
+        assert!(mgr.load(&x_bnd).is_err());
+
+        // Declared globals can still be assigned to
+        let y = test_utils::make_num(1.);
+        let y_bnd = y.binding.clone();
+        mgr.declare_global(y, None).unwrap();
+        let (mut var, _) = mgr.load(&y_bnd).unwrap();
+        var.t = JsType::JsNum(2.);
+        assert!(mgr.store(var, None).is_ok());
+    }
+
+    #[test]
+    fn test_call_strictness_is_lexical() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (f, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+        mgr.alloc(f, Some(f_ptr), DeclKind::Let).unwrap();
+        let f_unique = mgr.load(&f_bnd).unwrap().0.binding;
+
+        // A sloppy function stays sloppy when called from strict mode code
+        mgr.push_scope(&Exp::Undefined);
+        mgr.use_strict();
+        mgr.push_call(&f_unique);
+        assert!(!mgr.curr_scope.is_strict());
+        assert!(mgr.store(test_utils::make_num(1.), None).is_ok());
+        mgr.pop_scope(false).unwrap();
+        mgr.pop_scope(false).unwrap();
+
+        // A function defined in strict mode code is strict wherever it is called
+        mgr.push_scope(&Exp::Undefined);
+        mgr.use_strict();
+        let (g, g_ptr, g_bnd) = test_utils::make_fn(&None, &Vec::new());
+        mgr.alloc(g, Some(g_ptr), DeclKind::Let).unwrap();
+        let g_unique = mgr.load(&g_bnd).unwrap().0.binding;
+        mgr.pop_scope(false).unwrap();
+        mgr.push_call(&g_unique);
+        assert!(mgr.curr_scope.is_strict());
+        assert!(matches!(mgr.store(test_utils::make_num(1.), None), Err(GcError::Unresolved(_))));
+    }
+
+    #[test]
+    fn test_use_strict() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        mgr.push_scope(&Exp::Undefined);
+        mgr.use_strict();
+        mgr.push_scope(&Exp::Undefined);
+        // Strictness is inherited by nested scopes, but not by the enclosing one
+        assert!(mgr.curr_scope.is_strict());
+        mgr.pop_scope(false).unwrap();
+        mgr.pop_scope(false).unwrap();
+        assert!(!mgr.curr_scope.is_strict());
+        assert!(mgr.store(test_utils::make_num(1.), None).is_ok());
+    }
+
+    #[test]
+    fn test_load_store_slot() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        mgr.push_scope(&Exp::Undefined);
+        let slot = mgr.resolve(&x_bnd).unwrap();
+
+        let (var, _) = mgr.load_slot(slot).unwrap();
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        mgr.store_slot(slot, var, Some(y_ptr)).unwrap();
+        match mgr.load(&x_bnd).unwrap() {
+            (_, Some(JsPtrEnum::JsStr(ref s))) => assert_eq!(s.text, "y"),
+            _ => unreachable!(),
+        }
+        // Slots are subject to the heap limit like any other store
+        let max_bytes = mgr.alloc_box.borrow().byte_len();
+        mgr.alloc_box.borrow_mut().set_policy(GcPolicy { max_bytes: Some(max_bytes), ..GcPolicy::default() });
+        let (var, _) = mgr.load_slot(slot).unwrap();
+        let (_, z_ptr, _) = test_utils::make_str("a string that no longer fits in the heap");
+        assert!(matches!(mgr.store_slot(slot, var, Some(z_ptr)), Err(GcError::OutOfMemory)));
+    }
+
+    #[test]
+    fn test_load_ref() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        mgr.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (_, handle) = mgr.load_ref(&x_bnd).unwrap();
+        let handle = handle.unwrap();
+        match *handle.borrow() {
+            JsPtrEnum::JsStr(ref s) => assert_eq!(s.text, "x"),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn test_load_ref_shared_collect() {
+        let mut first = init_gc();
+        let mut second = ScopeManagerBuilder::new().alloc_box(first.alloc_box().clone()).build();
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        first.alloc(x, Some(x_ptr), DeclKind::Let).unwrap();
+
+        // The handle borrows the heap, which the other manager can't collect
+        let (_, handle) = first.load_ref(&x_bnd).unwrap();
+        let _handle = handle.unwrap();
+        second.curr_scope.collect(true);
+    }
+
+    #[test]
+    fn test_load_mut() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        let (s, s_ptr, s_bnd) = test_utils::make_str("s");
+        mgr.alloc_box.borrow_mut().alloc(s_bnd, s_ptr).unwrap();
+        let bytes = mgr.alloc_box.borrow().byte_len();
+
+        // Point the object at the string without copying the object
+        mgr.start_gc();
+        while !mgr.gc_step(1) {}
+        {
+            let (_, handle) = mgr.load_mut(&obj_bnd).unwrap();
+            let handle = handle.unwrap();
+            if let JsPtrEnum::JsObj(ref mut obj) = *handle.borrow_mut() {
+                obj.dict.insert(JsKey::JsSym("s".to_owned()), s);
+            };
+        }
+        // The write barrier ran when the handle was dropped, so the string
+        // survives the cycle
+        while !mgr.gc_step(1) {}
+        mgr.finish_gc();
+        assert_eq!(mgr.alloc_box.borrow().len(), 2);
+        assert!(mgr.alloc_box.borrow().byte_len() > bytes);
+    }
+
+    #[test]
+    fn test_load_mut_out_of_memory() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        let max_bytes = mgr.alloc_box.borrow().byte_len();
+        mgr.alloc_box.borrow_mut().set_policy(GcPolicy { max_bytes: Some(max_bytes), ..GcPolicy::default() });
+
+        // Growth through a handle is kept, but reported when it is finished
+        let res = {
+            let (_, handle) = mgr.load_mut(&obj_bnd).unwrap();
+            let handle = handle.unwrap();
+            if let JsPtrEnum::JsObj(ref mut obj) = *handle.borrow_mut() {
+                obj.dict.insert(JsKey::JsSym("n".to_owned()), test_utils::make_num(1.));
+            };
+            handle.finish()
+        };
+        assert!(matches!(res, Err(GcError::OutOfMemory)));
+        assert!(mgr.alloc_box.borrow().byte_len() > max_bytes);
+        let (s, s_ptr, _) = test_utils::make_str("s");
+        assert!(matches!(mgr.alloc(s, Some(s_ptr), DeclKind::Let), Err(GcError::OutOfMemory)));
+    }
+
+    #[test]
+    fn test_set_get_property() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        let n_key = JsKey::JsSym("n".to_owned());
+        let s_key = JsKey::JsSym("s".to_owned());
+
+        mgr.set_property(&obj_bnd, n_key.clone(), test_utils::make_num(1.), None).unwrap();
+        let (s, s_ptr, _) = test_utils::make_str("s");
+        mgr.set_property(&obj_bnd, s_key.clone(), s, Some(s_ptr)).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().len(), 2);
+
+        let (n, _) = mgr.get_property(&obj_bnd, &n_key).unwrap().unwrap();
+        assert!(matches!(n.t, JsType::JsNum(n) if n == 1.));
+        // The object's new child is traced
+        mgr.curr_scope.collect(true);
+        assert!(matches!(mgr.get_property(&obj_bnd, &s_key), Ok(Some((_, Some(JsPtrEnum::JsStr(_)))))));
+        assert!(mgr.get_property(&obj_bnd, &JsKey::JsSym("none".to_owned())).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_set_property_copy() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        let (s, s_ptr, s_bnd) = test_utils::make_str("s");
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        mgr.alloc(s, Some(s_ptr), DeclKind::Let).unwrap();
+
+        // The property gets its own copy of the variable's value
+        let key = JsKey::JsSym("s".to_owned());
+        let (s, _) = mgr.load(&s_bnd).unwrap();
+        mgr.set_property(&obj_bnd, key.clone(), s.clone(), None).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().len(), 3);
+        let (prop, _) = mgr.get_property(&obj_bnd, &key).unwrap().unwrap();
+        assert!(prop.binding != s.binding);
+
+        // Later stores to the variable don't reach the property
+        let (_, t_ptr, _) = test_utils::make_str("t");
+        mgr.store(s.clone(), Some(t_ptr)).unwrap();
+        let mut s = s;
+        s.t = JsType::JsNum(1.);
+        mgr.store(s, None).unwrap();
+        mgr.curr_scope.collect(true);
+        match mgr.get_property(&obj_bnd, &key).unwrap() {
+            Some((_, Some(JsPtrEnum::JsStr(ref s)))) => assert_eq!(s.text, "s"),
+            _ => unreachable!(),
+        }
+        assert!(mgr.verify().is_ok());
+    }
+
+    #[test]
+    fn test_delete_property() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        let key = JsKey::JsSym("s".to_owned());
+        let (s, s_ptr, _) = test_utils::make_str("s");
+        mgr.set_property(&obj_bnd, key.clone(), s, Some(s_ptr)).unwrap();
+
+        assert!(mgr.delete_property(&obj_bnd, &key).unwrap());
+        assert!(!mgr.delete_property(&obj_bnd, &key).unwrap());
+        mgr.curr_scope.collect(true);
+        assert_eq!(mgr.alloc_box.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_property_not_object() {
+        let alloc_box = test_utils::make_alloc_box();
+        let mut mgr = ScopeManager::new(alloc_box);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
+        let key = JsKey::JsSym("p".to_owned());
+        assert!(matches!(mgr.get_property(&x_bnd, &key), Err(GcError::NotObject(_))));
+        assert!(matches!(mgr.set_property(&x_bnd, key, test_utils::make_num(1.), None),
+                         Err(GcError::NotObject(_))));
+    }
+
+    #[test]
+    fn test_compacting_gc() {
+        let mut mgr = ScopeManagerBuilder::new().compact(true)
+                                                .heap_limit(Some(5), None)
+                                                .verify(true)
+                                                .build();
+        let global_this = mgr.global_this().unwrap();
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        mgr.alloc(x, None, DeclKind::Let).unwrap();
+        let (obj, obj_ptr, obj_bnd) = test_utils::make_obj(Vec::new(), mgr.alloc_box.clone());
+        mgr.alloc(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+        let key = JsKey::JsSym("s".to_owned());
+        let (s, s_ptr, _) = test_utils::make_str("s");
+        mgr.set_property(&obj_bnd, key.clone(), s, Some(s_ptr)).unwrap();
+        let (old_obj, _) = mgr.load(&obj_bnd).unwrap();
+
+        // The heap is full of garbage, so the store runs a compacting
+        // collection before it finds the variable
+        mgr.alloc_box.borrow_mut().alloc(Binding::anon(), test_utils::make_str("dead").1).unwrap();
+        mgr.alloc_box.borrow_mut().alloc(Binding::anon(), test_utils::make_str("dead").1).unwrap();
+        let collections = mgr.alloc_box.borrow().collections();
+        let (mut x, _) = mgr.load(&x_bnd).unwrap();
+        x.t = JsType::JsPtr(JsPtrTag::JsStr);
+        mgr.store(x, Some(test_utils::make_str("x").1)).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().collections(), collections + 1);
+        assert!(!mgr.alloc_box.borrow().forwarding().is_empty());
+        assert!(matches!(mgr.load(&x_bnd), Ok((_, Some(JsPtrEnum::JsStr(_))))));
+
+        // The object moved, and is reached through its new binding
+        let (new_obj, _) = mgr.load(&obj_bnd).unwrap();
+        assert!(new_obj.binding != old_obj.binding);
+        assert_eq!(new_obj.binding, mgr.alloc_box.borrow().forwarding()[&old_obj.binding]);
+        assert!(mgr.global_this().unwrap() != global_this);
+        match mgr.get_property(&obj_bnd, &key).unwrap() {
+            Some((_, Some(JsPtrEnum::JsStr(ref s)))) => assert_eq!(s.text, "s"),
+            _ => unreachable!(),
+        }
+
+        // A variable loaded before a compacting collection is stored to after it
+        let (x, _) = mgr.load(&x_bnd).unwrap();
+        mgr.push_scope(&Exp::Undefined);
+        mgr.pop_scope(true).unwrap();
+        mgr.store(x, Some(test_utils::make_str("y").1)).unwrap();
+        match mgr.load(&x_bnd).unwrap() {
+            (_, Some(JsPtrEnum::JsStr(ref s))) => assert_eq!(s.text, "y"),
+            _ => unreachable!(),
+        }
+
+        // A property set after a compacting collection lands in the live object
+        mgr.alloc_box.borrow_mut().alloc(Binding::anon(), test_utils::make_str("dead").1).unwrap();
+        let (t, t_ptr, _) = test_utils::make_str("t");
+        mgr.set_property(&obj_bnd, key.clone(), t, Some(t_ptr)).unwrap();
+        assert_eq!(mgr.alloc_box.borrow().collections(), collections + 3);
+        match mgr.get_property(&obj_bnd, &key).unwrap() {
+            Some((_, Some(JsPtrEnum::JsStr(ref s)))) => assert_eq!(s.text, "t"),
+            _ => unreachable!(),
+        }
+        assert!(mgr.verify().is_ok());
+    }
 }
@@ -0,0 +1,112 @@
+use js_types::js_var::{JsKey, JsPtrEnum, JsPtrTag, JsType, JsVar};
+use js_types::binding::Binding;
+
+use alloc::Heap;
+use alloc::policy::{size_of_prop, size_of_ptr};
+use gc_error::{GcError, Result};
+use super::ScopeManager;
+
+impl<H: Heap> ScopeManager<H> {
+    /// Return a copy of the property `key` of the object bound to `obj`, and
+    /// its heap value if it has one, or `None` if the object has no such
+    /// property.
+    pub fn get_property(&self, obj: &Binding, key: &JsKey) -> Result<Option<(JsVar, Option<JsPtrEnum>)>> {
+        let obj_bnd = try!(self.find_object(obj));
+        let var = match self.alloc_box.borrow().find(&obj_bnd) {
+            Some(alloc) => match *alloc.borrow() {
+                JsPtrEnum::JsObj(ref obj) => obj.dict.get(key).cloned(),
+                _ => None,
+            },
+            None => None,
+        };
+        let var = match var {
+            Some(var) => var,
+            None => return Ok(None),
+        };
+        if let JsType::JsPtr(_) = var.t {
+            let ptr = match self.alloc_box.borrow().find(&var.binding) {
+                Some(alloc) => alloc.borrow().clone(),
+                None => return Err(GcError::Load(var.binding.clone())),
+            };
+            Ok(Some((var, Some(ptr))))
+        } else {
+            Ok(Some((var, None)))
+        }
+    }
+
+    /// Set the property `key` of the object bound to `obj`, in place. If `ptr`
+    /// is given, it is allocated as the property's new heap value; a pointer
+    /// `var` without a `ptr` gives the property a copy of the value bound to
+    /// `var`, so that later stores to `var` don't affect it.
+    pub fn set_property(&mut self, obj: &Binding, key: JsKey, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        try!(self.find_object(obj));
+        match var.t {
+            JsType::JsPtr(tag) => {
+                let ptr = match ptr {
+                    Some(ptr) => ptr,
+                    None => match self.alloc_box.borrow().find(&self.forwarded(&var.binding)) {
+                        Some(alloc) => alloc.borrow().clone(),
+                        None => return Err(GcError::HeapUpdate),
+                    },
+                };
+                if !tag.eq_ptr_type(&ptr) { return Err(GcError::PtrAlloc); }
+                // Make room for the value and the object's growth, while the
+                // value isn't allocated yet and so can't be collected
+                try!(self.reserve(1, size_of_ptr(&ptr) + size_of_prop()));
+                var.binding = Binding::mangle(var.binding.clone());
+                try!(self.alloc_box.borrow_mut().alloc(var.binding.clone(), ptr));
+            },
+            _ => {
+                if let Some(_) = ptr { return Err(GcError::PtrAlloc); }
+                try!(self.reserve(0, size_of_prop()));
+            },
+        }
+        // Making room may have run a collection, so the object is only looked
+        // up for writing once it has
+        let obj_bnd = try!(self.find_object(obj));
+        let old_size = match self.alloc_box.borrow().find(&obj_bnd) {
+            Some(alloc) => {
+                let old_size = size_of_ptr(&*alloc.borrow());
+                if let JsPtrEnum::JsObj(ref mut obj) = *alloc.borrow_mut() {
+                    obj.dict.insert(key, var);
+                }
+                old_size
+            }
+            None => return Err(GcError::NotObject(obj.clone())),
+        };
+        // The object may now point at an unmarked or young allocation
+        self.alloc_box.borrow_mut().mutated(&obj_bnd, old_size)
+    }
+
+    /// Delete the property `key` of the object bound to `obj`, returning
+    /// whether it existed. Its heap value is freed by the next collection
+    /// unless it is still referenced from elsewhere.
+    pub fn delete_property(&mut self, obj: &Binding, key: &JsKey) -> Result<bool> {
+        let obj_bnd = try!(self.find_object(obj));
+        let (old_size, deleted) = match self.alloc_box.borrow().find(&obj_bnd) {
+            Some(alloc) => {
+                let old_size = size_of_ptr(&*alloc.borrow());
+                let deleted = match *alloc.borrow_mut() {
+                    JsPtrEnum::JsObj(ref mut obj) => obj.dict.remove(key).is_some(),
+                    _ => false,
+                };
+                (old_size, deleted)
+            }
+            None => return Err(GcError::NotObject(obj.clone())),
+        };
+        if deleted {
+            try!(self.alloc_box.borrow_mut().mutated(&obj_bnd, old_size));
+        }
+        Ok(deleted)
+    }
+
+    // Find the unique binding of the object bound to `obj`, which `find_var`
+    // only returns if it has a heap allocation.
+    fn find_object(&self, obj: &Binding) -> Result<Binding> {
+        let var = try!(self.find_var(obj));
+        if let JsType::JsPtr(JsPtrTag::JsObj) = var.t {
+            return Ok(var.binding);
+        }
+        Err(GcError::NotObject(obj.clone()))
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::hash_set::HashSet;
+
+use jsrs_common::ast::{Exp, Stmt};
+use js_types::js_fn::JsFnStruct;
+
+/// Find the names a function refers to but does not bind itself, i.e. the
+/// variables its closure has to capture. Returns `None` if the body contains
+/// syntax the analysis doesn't understand, in which case the caller must
+/// conservatively assume that every variable in scope is captured.
+pub fn free_vars(f: &JsFnStruct) -> Option<HashSet<String>> {
+    free_in_fn(&f.name, &f.params, &f.stmt)
+}
+
+fn free_in_fn(name: &Option<String>, params: &[String], body: &Stmt) -> Option<HashSet<String>> {
+    let mut used = HashSet::new();
+    // Declarations are hoisted to the top of the function, so a name declared
+    // anywhere in the body is local to the whole body.
+    let mut declared: HashSet<String> = params.iter().cloned().collect();
+    if let Some(ref name) = *name {
+        declared.insert(name.clone());
+    }
+    if !walk_stmt(body, &mut used, &mut declared) {
+        return None;
+    }
+    Some(used.difference(&declared).cloned().collect())
+}
+
+fn walk_stmt(stmt: &Stmt, used: &mut HashSet<String>, declared: &mut HashSet<String>) -> bool {
+    match *stmt {
+        Stmt::Empty => true,
+        Stmt::Assign(ref name, ref exp) => {
+            used.insert(name.clone());
+            walk_exp(exp, used)
+        }
+        Stmt::Decl(ref name, ref exp) => {
+            declared.insert(name.clone());
+            walk_exp(exp, used)
+        }
+        Stmt::BareExp(ref exp) | Stmt::Ret(ref exp) => walk_exp(exp, used),
+        Stmt::Seq(ref s1, ref s2) => walk_stmt(s1, used, declared) && walk_stmt(s2, used, declared),
+        _ => false,
+    }
+}
+
+fn walk_exp(exp: &Exp, used: &mut HashSet<String>) -> bool {
+    match *exp {
+        Exp::Undefined | Exp::Bool(_) | Exp::Float(_) => true,
+        Exp::Var(ref name) => {
+            used.insert(name.clone());
+            true
+        }
+        Exp::BinExp(ref e1, _, ref e2) => walk_exp(e1, used) && walk_exp(e2, used),
+        Exp::Call(ref f, ref args) => walk_exp(f, used) && args.iter().all(|arg| walk_exp(arg, used)),
+        Exp::Function(ref name, ref params, ref body) =>
+            match free_in_fn(name, params, body) {
+                Some(free) => {
+                    used.extend(free);
+                    true
+                }
+                None => false,
+            },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jsrs_common::ast::{BinOp, Exp, Stmt};
+    use js_types::js_fn::JsFnStruct;
+
+    fn var(s: &str) -> Exp {
+        Exp::Var(s.to_owned())
+    }
+
+    #[test]
+    fn test_free_vars() {
+        // function f(a) { var b = a + x; return b + y; }
+        let body = Stmt::Seq(box Stmt::Decl("b".to_owned(), Exp::BinExp(box var("a"), BinOp::Plus, box var("x"))),
+                             box Stmt::Ret(Exp::BinExp(box var("b"), BinOp::Plus, box var("y"))));
+        let f = JsFnStruct::new(&Some("f".to_owned()), &vec!["a".to_owned()], &body);
+        let free = free_vars(&f).unwrap();
+        assert_eq!(free.len(), 2);
+        assert!(free.contains("x"));
+        assert!(free.contains("y"));
+    }
+
+    #[test]
+    fn test_free_vars_nested_fn() {
+        // function f() { return function(a) { return a + x; }; }
+        let inner = Exp::Function(None, vec!["a".to_owned()],
+                                  box Stmt::Ret(Exp::BinExp(box var("a"), BinOp::Plus, box var("x"))));
+        let f = JsFnStruct::new(&None, &Vec::new(), &Stmt::Ret(inner));
+        let free = free_vars(&f).unwrap();
+        assert_eq!(free.len(), 1);
+        assert!(free.contains("x"));
+    }
+
+    #[test]
+    fn test_free_vars_empty() {
+        let f = JsFnStruct::new(&None, &Vec::new(), &Stmt::Empty);
+        assert!(free_vars(&f).unwrap().is_empty());
+    }
+}
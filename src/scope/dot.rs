@@ -0,0 +1,39 @@
+use std::fmt::{self, Write};
+
+use alloc::Heap;
+use alloc::dot::{heap_node, quote};
+use js_types::js_var::JsType;
+
+use super::Scope;
+
+impl<H: Heap> Scope<H> {
+    /// Write this scope and all of its ancestors as Graphviz nodes. Each local
+    /// binding gets its own node showing the mangled binding it maps to, with
+    /// an edge into the heap if it is a pointer. Roots are drawn with a double
+    /// border. `id` names this scope's node; its parent is named `id` + "^".
+    pub fn write_dot(&self, id: &str, out: &mut String) -> fmt::Result {
+        let node = quote(id);
+        try!(writeln!(out, "    {} [shape=box, label={}];",
+                      node, quote(&format!("{} ({:?}, {} roots)", id, self.tag, self.roots.borrow().len()))));
+        for (local, unique) in &self.locals {
+            let var_node = quote(&format!("{}:{}", id, local));
+            let peripheries = if self.roots.borrow().contains(unique) { 2 } else { 1 };
+            try!(writeln!(out, "    {} [shape=record, peripheries={}, label={}];",
+                          var_node, peripheries, quote(&format!("{} -> {}", local, unique))));
+            try!(writeln!(out, "    {} -> {} [arrowhead=none];", node, var_node));
+            if let Some(var) = self.stack_var(unique) {
+                if let JsType::JsPtr(_) = var.t {
+                    if self.heap.borrow().find(unique).is_some() {
+                        try!(writeln!(out, "    {} -> {};", var_node, heap_node(unique)));
+                    }
+                }
+            }
+        }
+        if let Some(ref parent) = self.parent {
+            let parent_id = format!("{}^", id);
+            try!(parent.write_dot(&parent_id, out));
+            try!(writeln!(out, "    {} -> {} [style=dashed, label=parent];", node, quote(&parent_id)));
+        }
+        Ok(())
+    }
+}
@@ -1,29 +1,65 @@
-use std::cell::RefCell;
-use std::collections::hash_map::{Entry, HashMap};
+mod capture;
+mod dot;
+
+use std::cell::{Ref, RefCell};
+use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
 use std::mem;
 use std::rc::Rc;
 
-use alloc::AllocBox;
+use alloc::{self, AllocBox, Heap};
 use gc_error::{GcError, Result};
 use js_types::js_var::{JsPtrEnum, JsPtrTag, JsType, JsVar};
 use js_types::binding::Binding;
-use js_types::allocator::Allocator;
+use verify::{Violation, VerifyReport};
 
 /// A logical scope in the AST. Represents any scoped block of Javascript code.
-/// roots: A set of all root references into the heap
+/// roots: A set of all root references into the heap, registered with the
+///        heap so that every collection respects it. A closure environment's
+///        roots are the heap bindings its functions keep alive, and aren't
+///        registered, since they are traced through those functions.
 /// parent: An optional parent scope, e.g. the caller of this function scope,
 ///         or the function that owns an `if` statement
 /// heap: A shared reference to the heap allocator.
-/// stack: The stack of the current scope, containing all variables allocated
-///        by this scope.
-pub struct Scope {
-    roots: HashSet<Binding>,
-    pub parent: Option<Box<Scope>>,
-    heap: Rc<RefCell<AllocBox>>,
+/// stack: The index in `frame` of every variable allocated by this scope, by
+///        its unique binding.
+/// env: For a function call or a closure environment, the binding of the
+///      function whose captured environment is its lexical parent, if any.
+/// hoisted: Bindings that have been declared but not yet initialised, and how
+///          they were declared.
+/// consts: Bindings declared with `const`.
+/// strict: Whether this scope's code runs in strict mode.
+/// frame: The variables of this scope in declaration order, indexed by the
+///        `Slot`s that bindings resolve to.
+pub struct Scope<H: Heap = AllocBox> {
+    roots: Rc<RefCell<HashSet<Binding>>>,
+    pub parent: Option<Box<Scope<H>>>,
+    heap: Rc<RefCell<H>>,
     locals: HashMap<Binding, Binding>,
-    stack: HashMap<Binding, JsVar>,
+    stack: HashMap<Binding, usize>,
     tag: ScopeTag,
+    env: Option<Binding>,
+    hoisted: HashMap<Binding, DeclKind>,
+    consts: HashSet<Binding>,
+    strict: bool,
+    frame: Vec<JsVar>,
+}
+
+/// The location of a variable resolved ahead of time: the number of scopes
+/// above the current one that own it, and its index in that scope's frame.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+/// How a variable was declared. `var` declarations belong to the enclosing
+/// function call, while `let` and `const` belong to the block they're in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeclKind {
+    Var,
+    Let,
+    Const,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -32,38 +68,81 @@ pub enum ScopeTag {
     Block,
 }
 
-impl Scope {
+impl<H: Heap> Scope<H> {
     /// Create a new, parentless scope node.
-    pub fn new(tag: ScopeTag, heap: &Rc<RefCell<AllocBox>>) -> Scope {
+    pub fn new(tag: ScopeTag, heap: &Rc<RefCell<H>>) -> Scope<H> {
+        let scope = Scope::unregistered(tag, heap);
+        heap.borrow_mut().register_roots(&scope.roots);
+        scope
+    }
+
+    // Create a scope whose roots aren't registered with the heap, i.e. a
+    // closure environment.
+    fn unregistered(tag: ScopeTag, heap: &Rc<RefCell<H>>) -> Scope<H> {
         Scope {
-            roots: HashSet::new(),
+            roots: Rc::new(RefCell::new(HashSet::new())),
             parent: None,
             heap: heap.clone(),
             locals: HashMap::new(),
             stack: HashMap::new(),
             tag: tag,
+            env: None,
+            hoisted: HashMap::new(),
+            consts: HashSet::new(),
+            strict: false,
+            frame: Vec::new(),
         }
     }
 
     /// Sets the parent of a scope, and clones and unions its root bindings.
-    pub fn set_parent(&mut self, parent: Scope) {
-        self.roots = self.roots.union(&parent.roots).cloned().collect();
+    /// A block nested inside strict mode code is strict too, while a call is
+    /// only strict if its function was defined in strict mode code.
+    pub fn set_parent(&mut self, parent: Scope<H>) {
+        self.roots.borrow_mut().extend(parent.roots.borrow().iter().cloned());
+        if self.tag == ScopeTag::Block {
+            self.strict = self.strict || parent.strict;
+        }
         self.parent = Some(box parent);
     }
 
     /// Push a new JsVar onto the stack, and maybe allocate a pointer in the heap.
-    pub fn push_var(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+    /// A `var` is pushed onto the stack of the nearest function call scope instead.
+    pub fn push_var(&mut self, var: JsVar, ptr: Option<JsPtrEnum>, kind: DeclKind) -> Result<()> {
+        self.declare_var(var, ptr, kind).map(|_| ())
+    }
+
+    // Push a new variable, returning its unique binding.
+    fn declare_var(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>, kind: DeclKind) -> Result<Binding> {
+        if kind == DeclKind::Var && self.tag == ScopeTag::Block {
+            if let Some(ref mut parent) = self.parent {
+                let is_ptr = matches!(var.t, JsType::JsPtr(_));
+                let unique = try!(parent.declare_var(var, ptr, kind));
+                // Collections are rooted at the innermost scope, so it must root the var too
+                if is_ptr {
+                    self.roots.borrow_mut().insert(unique.clone());
+                }
+                return Ok(unique);
+            }
+        }
         // Copy the local binding of the variable (e.g. "x")
         let local = var.binding.clone();
         // Mangle the local binding to create a globally-unique name for the variable,
-        // so that it can be safely allocated anywhere without name collisions.
-        var.binding = Binding::mangle(var.binding.clone());
+        // so that it can be safely allocated anywhere without name collisions. A hoisted
+        // binding has already been mangled, and is now initialised.
+        var.binding = match self.locals.get(&local) {
+            Some(unique) if self.hoisted.contains_key(unique) => unique.clone(),
+            _ => Binding::mangle(var.binding.clone()),
+        };
+        self.hoisted.remove(&var.binding);
+        if kind == DeclKind::Const {
+            self.consts.insert(var.binding.clone());
+        }
         // Maybe insert the variable's pointer data into the heap
         let res = match var.t {
             JsType::JsPtr(_) =>
                 if let Some(ptr) = ptr {
                     // Creating a new pointer creates a new root
-                    self.roots.insert(var.binding.clone());
+                    self.roots.borrow_mut().insert(var.binding.clone());
                     self.heap.borrow_mut().alloc(var.binding.clone(), ptr)
                 } else {
                     return Err(GcError::PtrAlloc);
@@ -72,31 +151,215 @@ impl Scope {
         };
         // Create a mapping from the local binding to the unique binding
         self.locals.insert(local, var.binding.clone());
-        // Push the unique binding onto the stack
-        self.stack.insert(var.binding.clone(), var);
+        let unique = var.binding.clone();
+        // Push the variable onto the stack. A hoisted binding keeps its slot.
+        self.put_var(var);
+        res.map(|_| unique)
+    }
+
+    /// Declare a variable ahead of its initialisation, as happens at the start
+    /// of the scope that owns it. Until it is initialised with `push_var`, a
+    /// `var` reads as undefined, while reading or writing a `let` or `const`
+    /// is an error: the binding is in its temporal dead zone.
+    pub fn hoist(&mut self, local: Binding, kind: DeclKind) {
+        if kind == DeclKind::Var && self.tag == ScopeTag::Block {
+            if let Some(ref mut parent) = self.parent {
+                return parent.hoist(local, kind);
+            }
+        }
+        if self.locals.contains_key(&local) {
+            return;
+        }
+        let mut var = JsVar::new(JsType::JsUndef);
+        var.binding = Binding::mangle(local.clone());
+        self.hoisted.insert(var.binding.clone(), kind);
+        self.locals.insert(local, var.binding.clone());
+        self.put_var(var);
+    }
+
+    /// Whether the variable with unique binding `bnd` has been declared with
+    /// `let` or `const`, but not yet initialised.
+    pub fn in_tdz(&self, bnd: &Binding) -> bool {
+        self.owner(bnd).map_or(false, |scope|
+                               scope.hoisted.get(bnd).map_or(false, |kind| *kind != DeclKind::Var))
+    }
+
+    /// Whether the variable with unique binding `bnd` was declared with `const`.
+    pub fn is_const(&self, bnd: &Binding) -> bool {
+        self.owner(bnd).map_or(false, |scope| scope.consts.contains(bnd))
+    }
+
+    /// Whether the scope that owns the variable with unique binding `bnd`,
+    /// either this one or an ancestor, is strict, or `None` if none of them do.
+    pub fn is_strict_owner(&self, bnd: &Binding) -> Option<bool> {
+        self.owner(bnd).map(|scope| scope.strict)
+    }
+
+    // The scope, either this one or an ancestor, whose stack holds `bnd`.
+    fn owner(&self, bnd: &Binding) -> Option<&Scope<H>> {
+        if self.stack.contains_key(bnd) {
+            Some(self)
+        } else {
+            self.parent.as_ref().and_then(|parent| parent.owner(bnd))
+        }
+    }
+
+    // Take over a variable of an exiting scope, and whether it was declared `const`.
+    fn rebind_var(&mut self, local: Binding, unique: Binding, var: JsVar, is_const: bool) {
+        if is_const {
+            self.consts.insert(unique.clone());
+        }
+        self.locals.insert(local, unique);
+        self.put_var(var);
+    }
+
+    // Put a variable on the stack, replacing the one with the same unique
+    // binding in its slot, if there is one.
+    fn put_var(&mut self, var: JsVar) {
+        let index = self.stack.get(&var.binding).cloned();
+        match index {
+            Some(index) => self.frame[index] = var,
+            None => {
+                self.stack.insert(var.binding.clone(), self.frame.len());
+                self.frame.push(var);
+            },
+        }
+    }
+
+    // The variable on the stack with unique binding `unique`.
+    fn stack_var(&self, unique: &Binding) -> Option<&JsVar> {
+        self.stack.get(unique).map(|&index| &self.frame[index])
+    }
+
+    /// Resolve a local binding to the slot of its variable, searching upwards
+    /// by the same rules as `get_var_copy`. A slot stays valid for as long as
+    /// the scopes between this one and the variable's owner exist, so a
+    /// function's bindings can be resolved once, ahead of time.
+    pub fn resolve(&self, local: &Binding) -> Option<Slot> {
+        if let Some(unique) = self.locals.get(local) {
+            self.stack.get(unique).map(|&index| Slot { depth: 0, index: index })
+        } else if self.tag == ScopeTag::Call {
+            None
+        } else if let Some(ref parent) = self.parent {
+            parent.resolve(local).map(|slot| Slot { depth: slot.depth + 1, index: slot.index })
+        } else { None }
+    }
+
+    /// The unique binding of the variable in a slot.
+    pub fn slot_binding(&self, slot: Slot) -> Option<&Binding> {
+        self.slot_var(slot).map(|(_, var)| &var.binding)
+    }
+
+    // The variable in a slot, and the scope that owns it. A slot whose
+    // variable was dropped by `pop_dead_roots` is empty.
+    fn slot_var(&self, slot: Slot) -> Option<(&Scope<H>, &JsVar)> {
+        let scope = match self.ancestor(slot.depth) {
+            Some(scope) => scope,
+            None => return None,
+        };
+        match scope.frame.get(slot.index) {
+            Some(var) if scope.stack.get(&var.binding) == Some(&slot.index) => Some((scope, var)),
+            _ => None,
+        }
+    }
+
+    /// Return a copy of the variable in a slot, and its heap value if it has
+    /// one, without searching for it by name.
+    pub fn load_slot(&self, slot: Slot) -> Result<(JsVar, Option<JsPtrEnum>)> {
+        let (scope, var) = match self.slot_var(slot) {
+            Some(found) => found,
+            None => return Err(GcError::Slot(slot.depth, slot.index)),
+        };
+        if scope.hoisted.get(&var.binding).map_or(false, |kind| *kind != DeclKind::Var) {
+            return Err(GcError::Uninitialized(var.binding.clone()));
+        }
+        match var.t {
+            JsType::JsPtr(_) =>
+                match self.heap.borrow().find(&var.binding) {
+                    Some(alloc) => Ok((var.clone(), Some(alloc.borrow().clone()))),
+                    None => Err(GcError::Load(var.binding.clone())),
+                },
+            _ => Ok((var.clone(), None)),
+        }
+    }
+
+    /// Update the variable in a slot, as `update_var` would.
+    pub fn store_slot(&mut self, slot: Slot, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
+        self.assign_slot(slot, var, ptr).map(|_| ())
+    }
+
+    // Store into a slot, returning the variable's root, if it is a pointer.
+    fn assign_slot(&mut self, slot: Slot, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<Option<Binding>> {
+        var.binding = match self.slot_binding(slot) {
+            Some(bnd) => bnd.clone(),
+            None => return Err(GcError::Slot(slot.depth, slot.index)),
+        };
+        if slot.depth == 0 {
+            return self.assign(var, ptr);
+        }
+        let bnd = var.binding.clone();
+        let res = match self.parent {
+            Some(ref mut parent) =>
+                parent.assign_slot(Slot { depth: slot.depth - 1, index: slot.index }, var, ptr),
+            None => return Err(GcError::Slot(slot.depth, slot.index)),
+        };
+        if let Ok(ref root) = res {
+            self.sync_root(&bnd, root.clone());
+        }
         res
     }
 
-    fn rebind_var(&mut self, local: Binding, unique: Binding, var: JsVar) {
-        self.locals.insert(local, unique.clone());
-        self.stack.insert(unique, var);
+    // This scope, or the ancestor `depth` levels above it.
+    fn ancestor(&self, depth: usize) -> Option<&Scope<H>> {
+        if depth == 0 {
+            Some(self)
+        } else {
+            self.parent.as_ref().and_then(|parent| parent.ancestor(depth - 1))
+        }
+    }
+
+    // Keep our copy of a root of an ancestor in agreement with it after the
+    // ancestor's variable `bnd` is updated, and now has the root `root`.
+    fn sync_root(&mut self, bnd: &Binding, root: Option<Binding>) {
+        self.roots.borrow_mut().remove(bnd);
+        if let Some(root) = root {
+            self.roots.borrow_mut().insert(root);
+        }
     }
 
     /// Return an optional copy of a variable and an optional pointer into the heap.
     pub fn get_var_copy(&self, local: &Binding) -> Option<(JsVar, Option<JsPtrEnum>)> {
+        let var = match self.get_var(local) {
+            Some(var) => var,
+            None => return None,
+        };
+        if let JsType::JsPtr(_) = var.t {
+            let ptr = match self.heap.borrow().find(&var.binding) {
+                Some(alloc) => alloc.borrow().clone(),
+                None => return None,
+            };
+            Some((var, Some(ptr)))
+        } else {
+            Some((var, None))
+        }
+    }
+
+    /// Like `get_var_copy`, but return only the variable, leaving its heap
+    /// value, if any, to be looked up by its unique binding.
+    pub fn get_var(&self, local: &Binding) -> Option<JsVar> {
         if let Some(unique) = self.locals.get(local) {
-            if let Some(var) = self.stack.get(unique) {
+            if let Some(var) = self.stack_var(unique) {
                 match var.t {
                     JsType::JsPtr(_) => {
-                        if let Some(alloc) = self.heap.borrow().find_id(unique) {
-                            Some((var.clone(), Some(alloc.borrow().clone())))
+                        if self.heap.borrow().find(unique).is_some() {
+                            Some(var.clone())
                         } else {
                             // This case should be impossible unless you have an
                             // invalid ptr, which should also be impossible.
                             None
                         }
                     },
-                    _ => Some((var.clone(), None)),
+                    _ => Some(var.clone()),
                 }
             } else { None }
         } else if self.tag == ScopeTag::Call {
@@ -109,83 +372,382 @@ impl Scope {
             // its parent scope, so it should not do this lookup. If the overall
             // lookup fails, the ScopeManager will check the global scope.
             if let Some(ref parent) = self.parent {
-                parent.get_var_copy(local)
+                parent.get_var(local)
             } else { None }
         }
     }
 
-    /// Try to update a variable that's been allocated.
+    /// Try to update a variable that's been allocated. Like `get_var_copy`, this
+    /// searches upwards through enclosing blocks, and updates the variable in
+    /// the scope that owns it.
+    ///
+    /// A pointer variable that changes type needs a new allocation, or none.
+    /// Its old allocation may still be referenced from elsewhere, so it is
+    /// never freed here, only unrooted and left for the collector, and the
+    /// variable moves to a fresh unique binding, which `load` returns from
+    /// then on.
     pub fn update_var(&mut self, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<()> {
-        match var.t {
+        self.assign(var, ptr).map(|_| ())
+    }
+
+    // Update a variable, returning its root, if it is a pointer.
+    fn assign(&mut self, mut var: JsVar, ptr: Option<JsPtrEnum>) -> Result<Option<Binding>> {
+        if self.in_tdz(&var.binding) {
+            return Err(GcError::Uninitialized(var.binding));
+        }
+        if self.is_const(&var.binding) {
+            return Err(GcError::ConstAssign(var.binding));
+        }
+        let new_tag = match var.t {
             JsType::JsPtr(tag) =>
-                if let Some(ptr) = ptr {
-                    // A new root was potentially created
+                match ptr {
                     // If the pointer and its underlying type are not equal, return an error.
-                    if !tag.eq_ptr_type(&ptr) { return Err(GcError::PtrAlloc); }
-                    self.roots.insert(var.binding.clone());
-                    self.heap.borrow_mut().update_ptr(&var.binding, ptr)
-                } else {
-                    Err(GcError::PtrAlloc)
+                    Some(ref ptr) if !tag.eq_ptr_type(ptr) => return Err(GcError::PtrAlloc),
+                    Some(_) => Some(tag),
+                    None => return Err(GcError::PtrAlloc),
                 },
-            _ => {
-                if let Some(_) = ptr { return Err(GcError::PtrAlloc); }
-                if let Entry::Occupied(mut view) = self.stack.entry(var.binding.clone()) {
-                    // A root was potentially removed
-                    self.roots.remove(&var.binding);
-                    *view.get_mut() = var;
-                    return Ok(());
-                } else {
-                    Err(GcError::Store(var, ptr))
+            _ => if let Some(_) = ptr { return Err(GcError::PtrAlloc); } else { None },
+        };
+        let old_tag = match self.stack_var(&var.binding) {
+            Some(old) => if let JsType::JsPtr(tag) = old.t { Some(tag) } else { None },
+            None => return self.update_parent_var(var, ptr),
+        };
+        // A pointer that changes type can't be updated in place. Its old
+        // allocation may still be referenced from elsewhere, so rather than
+        // being freed, it is left behind for the collector, and the variable
+        // moves to a fresh binding.
+        let old_bnd = var.binding.clone();
+        let moved = old_tag.is_some() && old_tag != new_tag;
+        if moved {
+            var.binding = Binding::mangle(var.binding.clone());
+        }
+        if let Some(ptr) = ptr {
+            let mut heap = self.heap.borrow_mut();
+            if old_tag == new_tag {
+                // A root created mid-cycle must not be left white
+                heap.shade(&var.binding);
+                try!(heap.update_ptr(&var.binding, ptr));
+            } else {
+                try!(heap.alloc(var.binding.clone(), ptr));
+            }
+        }
+        if moved {
+            // Closures that captured the variable follow it
+            self.heap.borrow_mut().recapture(&old_bnd, &var.binding);
+        }
+        let root = new_tag.map(|_| var.binding.clone());
+        self.sync_root(&old_bnd, root.clone());
+        if var.binding != old_bnd {
+            self.rebind_unique(&old_bnd, &var.binding);
+        }
+        self.hoisted.remove(&var.binding);
+        self.put_var(var);
+        Ok(root)
+    }
+
+    // Move the variable with unique binding `old` to the unique binding `new`,
+    // keeping its local binding and its slot.
+    fn rebind_unique(&mut self, old: &Binding, new: &Binding) {
+        if let Some(index) = self.stack.remove(old) {
+            self.stack.insert(new.clone(), index);
+        }
+        self.hoisted.remove(old);
+        for unique in self.locals.values_mut() {
+            if unique == old {
+                *unique = new.clone();
+            }
+        }
+    }
+
+    // Update a variable owned by an enclosing block. A function call does not
+    // have access to its parent's variables.
+    fn update_parent_var(&mut self, var: JsVar, ptr: Option<JsPtrEnum>) -> Result<Option<Binding>> {
+        if self.tag == ScopeTag::Call {
+            return Err(GcError::Store(var, ptr));
+        }
+        let bnd = var.binding.clone();
+        let res = match self.parent {
+            Some(ref mut parent) => parent.assign(var, ptr),
+            None => return Err(GcError::Store(var, ptr)),
+        };
+        if let Ok(ref root) = res {
+            self.sync_root(&bnd, root.clone());
+        }
+        res
+    }
+
+    /// Attach the captured environment of the closure bound to `fn_bnd` as the
+    /// lexical parent of this scope.
+    pub fn set_env(&mut self, fn_bnd: Binding) {
+        self.env = Some(fn_bnd);
+    }
+
+    /// The closure environment of the function call this scope belongs to,
+    /// found by searching upwards through any enclosing blocks.
+    pub fn env(&self) -> Option<&Binding> {
+        if self.tag == ScopeTag::Call {
+            self.env.as_ref()
+        } else if let Some(ref parent) = self.parent {
+            parent.env()
+        } else { None }
+    }
+
+    /// Whether the variable with unique binding `bnd` was allocated in this scope.
+    pub fn defines(&self, bnd: &Binding) -> bool {
+        self.stack.contains_key(bnd)
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Root a heap allocation that isn't owned by any scope.
+    pub fn add_root(&mut self, bnd: Binding) {
+        self.roots.borrow_mut().insert(bnd);
+    }
+
+    pub fn roots(&self) -> Ref<HashSet<Binding>> {
+        self.roots.borrow()
+    }
+
+    /// Every root of this scope, paired with the local binding it is known by
+    /// in this scope or one of its ancestors. Roots without a local name are
+    /// paired with themselves.
+    pub fn named_roots(&self) -> Vec<(Binding, Binding)> {
+        let mut named = Vec::new();
+        let mut unnamed = self.roots.borrow().clone();
+        let mut scope = Some(self);
+        while let Some(curr) = scope {
+            for (local, unique) in &curr.locals {
+                if unnamed.remove(unique) {
+                    named.push((local.clone(), unique.clone()));
                 }
-            },
+            }
+            scope = curr.parent.as_ref().map(|parent| &**parent);
+        }
+        named.extend(unnamed.into_iter().map(|bnd| (bnd.clone(), bnd)));
+        named
+    }
+
+    /// Check that every local binding of this scope and its ancestors is on
+    /// the stack, and that every pointer on the stack has a heap allocation of
+    /// the matching type.
+    pub fn verify_into(&self, report: &mut VerifyReport) {
+        for (local, unique) in &self.locals {
+            if !self.stack.contains_key(unique) {
+                report.push(Violation::DanglingLocal(local.clone(), unique.clone()));
+            }
+        }
+        for (unique, &index) in &self.stack {
+            if let JsType::JsPtr(tag) = self.frame[index].t {
+                match self.heap.borrow().find(unique) {
+                    Some(alloc) => if !tag.eq_ptr_type(&*alloc.borrow()) {
+                        report.push(Violation::TagMismatch(unique.clone()));
+                    },
+                    None => report.push(Violation::MissingAlloc(unique.clone())),
+                }
+            }
+        }
+        if let Some(ref parent) = self.parent {
+            parent.verify_into(report);
+        }
+    }
+
+    /// Run a collection rooted at this scope, follow any allocations it moved,
+    /// then drop any dead roots. Unless `full` is set, a generational heap may
+    /// only collect its nursery.
+    pub fn collect(&mut self, full: bool) {
+        // The heap updates the registered root sets if it compacts, so it can't
+        // be handed one that is borrowed
+        let roots = self.roots.borrow().clone();
+        if full {
+            self.heap.borrow_mut().collect(&roots);
+        } else {
+            self.heap.borrow_mut().collect_garbage(&roots);
+        }
+        let heap = self.heap.clone();
+        if let Some(forwarding) = heap.borrow().forwarding() {
+            self.remap(forwarding);
+        }
+        self.pop_dead_roots();
+    }
+
+    /// Point every variable of this scope and its ancestors whose allocation
+    /// was moved by a compacting collection at the binding it was moved to.
+    pub fn remap(&mut self, forwarding: &HashMap<Binding, Binding>) {
+        if forwarding.is_empty() { return; }
+        let roots: HashSet<Binding> = self.roots.borrow()
+                                                .iter()
+                                                .map(|bnd| alloc::forwarded(forwarding, bnd))
+                                                .collect();
+        *self.roots.borrow_mut() = roots;
+        for unique in self.locals.values_mut() {
+            *unique = alloc::forwarded(forwarding, unique);
+        }
+        let stack = mem::replace(&mut self.stack, HashMap::new());
+        for (unique, index) in stack {
+            self.stack.insert(alloc::forwarded(forwarding, &unique), index);
+        }
+        let consts = self.consts.iter().map(|bnd| alloc::forwarded(forwarding, bnd)).collect();
+        self.consts = consts;
+        for var in &mut self.frame {
+            var.binding = alloc::forwarded(forwarding, &var.binding);
+        }
+        if let Some(ref mut env) = self.env {
+            *env = alloc::forwarded(forwarding, env);
+        }
+        if let Some(ref mut parent) = self.parent {
+            parent.remap(forwarding);
+        }
+    }
+
+    /// Pop all of the roots whose heap allocations were deleted by a collection,
+    /// along with the variables bound to them in this scope and its ancestors.
+    /// Their slots are left empty rather than reused, so slots resolved
+    /// earlier don't move, but no longer load.
+    pub fn pop_dead_roots(&mut self) {
+        let dead: HashSet<Binding> = {
+            let heap = self.heap.borrow();
+            self.roots.borrow().iter().filter(|bnd| heap.find(bnd).is_none()).cloned().collect()
+        };
+        if !dead.is_empty() {
+            self.drop_vars(&dead);
+        }
+    }
+
+    // Forget the variables with the given unique bindings, in this scope and
+    // its ancestors.
+    fn drop_vars(&mut self, dead: &HashSet<Binding>) {
+        for bnd in dead {
+            self.roots.borrow_mut().remove(bnd);
+            self.stack.remove(bnd);
+            self.consts.remove(bnd);
+        }
+        let locals: Vec<Binding> = self.locals.iter()
+                                              .filter(|&(_, unique)| dead.contains(unique))
+                                              .map(|(local, _)| local.clone())
+                                              .collect();
+        for local in locals {
+            self.locals.remove(&local);
+        }
+        if let Some(ref mut parent) = self.parent {
+            parent.drop_vars(dead);
         }
     }
 
+    /// Whether this is the environment of a closure that is still reachable,
+    /// i.e. whether any of the functions defined in it are still allocated.
+    pub fn is_live_closure(&self) -> bool {
+        let heap = self.heap.borrow();
+        self.stack.iter().any(|(bnd, &index)|
+                              matches!(self.frame[index].t, JsType::JsPtr(JsPtrTag::JsFn)) &&
+                              heap.find(bnd).is_some())
+    }
+
+    // The local bindings referred to by the bodies of the functions defined in this scope, or
+    // `None` if any of them can't be analysed.
+    fn captured_locals(&self) -> Option<HashSet<Binding>> {
+        let heap = self.heap.borrow();
+        let mut names = HashSet::new();
+        for (bnd, &index) in &self.stack {
+            if !matches!(self.frame[index].t, JsType::JsPtr(JsPtrTag::JsFn)) {
+                continue;
+            }
+            let alloc = match heap.find(bnd) {
+                Some(alloc) => alloc,
+                None => return None,
+            };
+            let free = match *alloc.borrow() {
+                JsPtrEnum::JsFn(ref f) => capture::free_vars(f),
+                _ => None,
+            };
+            match free {
+                Some(free) => names.extend(free),
+                None => return None,
+            }
+        }
+        Some(names.into_iter().map(Binding::new).collect())
+    }
+
     /// Called when a scope exits. Transfers the stack of this scope to its parent,
     /// and returns the parent scope, which may be `None`.
-    pub fn transfer_stack(&mut self, closures: &mut Vec<Scope>, gc_yield: bool) -> Result<Option<Box<Scope>>> {
+    pub fn transfer_stack(&mut self, closures: &mut Vec<Scope<H>>, gc_yield: bool) -> Result<Option<Box<Scope<H>>>> {
         if gc_yield {
             // The interpreter says we can GC now
-            self.heap.borrow_mut().mark_roots(&self.roots);
-            self.heap.borrow_mut().mark_ptrs();
-            self.heap.borrow_mut().sweep_ptrs();
-            // Pop all of the roots we just deleted
-            for bnd in &self.roots {
-                if let None = self.heap.borrow().find_id(bnd) {
-                    self.stack.remove(bnd);
-                }
-            }
+            self.collect(false);
         }
+        let captures = self.captured_locals();
+        let env = self.env().cloned();
         if let Some(ref mut parent) = self.parent {
-            let returning_closure = self.stack.iter()
-                                              .any(|(_, v)|
-                                                   matches!(v.t, JsType::JsPtr(JsPtrTag::JsFn)));
-            // If we're returning a closure, conservatively assume the closure takes ownership of
-            // every binding defined in this scope, so it must all live into the parent scope.
+            let frame = &self.frame;
+            let returning_closure = self.stack.values()
+                                              .any(|&index|
+                                                   matches!(frame[index].t, JsType::JsPtr(JsPtrTag::JsFn)));
+            // If we're returning a closure, the closure takes ownership of every binding its body
+            // refers to, so they must all live into the parent scope. If the body couldn't be
+            // analysed, conservatively assume it refers to everything.
             if returning_closure {
-                let mut closure_scope = Scope::new(ScopeTag::Call, &self.heap);
+                let mut closure_scope = Scope::unregistered(ScopeTag::Call, &self.heap);
+                // A closure defined inside another closure can see its environment too
+                closure_scope.env = env;
+                // Calls to the closure's functions are strict if the code defining them was
+                closure_scope.strict = self.strict;
+                let mut fns = Vec::new();
+                let mut captured = HashSet::new();
                 for (local, unique) in self.locals.drain() {
                     let var = match self.stack.remove(&unique) {
-                        Some(var) => var,
+                        Some(index) => self.frame[index].clone(),
                         None => return Err(GcError::Scope),
                     };
-                    closure_scope.rebind_var(local, unique, var);
+                    let is_const = self.consts.remove(&unique);
+                    let is_fn = matches!(var.t, JsType::JsPtr(JsPtrTag::JsFn));
+                    if !is_fn && !captures.as_ref().map_or(true, |c| c.contains(&local)) {
+                        // Not captured, so it is treated like any other binding of this scope.
+                        if let JsType::JsPtr(_) = var.t {
+                            parent.rebind_var(local, unique, var, is_const);
+                        }
+                        continue;
+                    }
+                    if is_fn { fns.push(unique.clone()); }
+                    // Primitives are captured too, since they may be assigned a pointer later
+                    captured.insert(unique.clone());
+                    if let JsType::JsPtr(_) = var.t {
+                        closure_scope.roots.borrow_mut().insert(unique.clone());
+                    }
+                    closure_scope.rebind_var(local, unique, var, is_const);
                 };
+                // The captured heap bindings are traced through the functions that close over
+                // them, rather than being roots, so they die along with those functions.
+                for fn_bnd in &fns {
+                    let mut env = captured.clone();
+                    env.remove(fn_bnd);
+                    self.heap.borrow_mut().set_fn_env(fn_bnd.clone(), env);
+                }
+                for bnd in &captured {
+                    if !fns.contains(bnd) {
+                        self.roots.borrow_mut().remove(bnd);
+                    }
+                }
                 closures.push(closure_scope);
             } else {
                 for (local, unique) in self.locals.drain() {
                     let var = match self.stack.remove(&unique) {
-                        Some(var) => var,
+                        Some(index) => self.frame[index].clone(),
                         None => return Err(GcError::Scope),
                     };
                     // Rebind all heap-allocated variables into the parent scope, so they may be
                     // GC'd at a later time.
                     if let JsType::JsPtr(_) = var.t {
-                        parent.rebind_var(local, unique, var);
+                        let is_const = self.consts.remove(&unique);
+                        parent.rebind_var(local, unique, var, is_const);
                     }
                 }
             }
-            parent.roots = parent.roots.union(&self.roots).cloned().collect();
+            parent.roots.borrow_mut().extend(self.roots.borrow().iter().cloned());
         }
         Ok(mem::replace(&mut self.parent, None))
     }
@@ -198,23 +760,33 @@ mod tests {
 
     use std::cell::RefCell;
     use std::collections::hash_map::HashMap;
+    use std::collections::hash_set::HashSet;
     use std::rc::Rc;
 
-    use alloc::AllocBox;
+    use alloc::{AllocBox, GcConfig};
     use gc_error::GcError;
-    use js_types::js_var::{JsVar, JsPtrEnum, JsKey, JsType};
+    use jsrs_common::ast::{Exp, Stmt};
+    use js_types::js_var::{JsVar, JsPtrEnum, JsPtrTag, JsKey, JsType};
     use js_types::binding::Binding;
     use js_types::js_str::JsStrStruct;
     use test_utils;
+    use verify::{Violation, VerifyReport};
 
     fn new_scope_as_child(parent: Scope, tag: ScopeTag, heap: &Rc<RefCell<AllocBox>>) -> Scope {
+        let roots = parent.roots.borrow().clone();
+        let strict = parent.strict;
         Scope {
-            roots: parent.roots.clone(),
+            roots: Rc::new(RefCell::new(roots)),
             parent: Some(box parent),
             heap: heap.clone(),
             locals: HashMap::new(),
             stack: HashMap::new(),
             tag: tag,
+            env: None,
+            hoisted: HashMap::new(),
+            consts: HashSet::new(),
+            strict: strict,
+            frame: Vec::new(),
         }
     }
 
@@ -248,10 +820,10 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut test_scope = Scope::new(ScopeTag::Block, &heap);
         let (var, ptr, _) = test_utils::make_str("test");
-        assert!(test_scope.push_var(var, Some(ptr)).is_ok());
+        assert!(test_scope.push_var(var, Some(ptr), DeclKind::Let).is_ok());
         assert_eq!(test_scope.heap.borrow().len(), 1);
         let var = test_utils::make_num(1.);
-        assert!(test_scope.push_var(var, None).is_ok());
+        assert!(test_scope.push_var(var, None, DeclKind::Let).is_ok());
         assert_eq!(test_scope.heap.borrow().len(), 1);
     }
 
@@ -260,12 +832,12 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut test_scope = Scope::new(ScopeTag::Block, &heap);
         let (var, ptr, _) = test_utils::make_str("test");
-        let res = test_scope.push_var(var, None);
+        let res = test_scope.push_var(var, None, DeclKind::Let);
         assert!(res.is_err());
         assert!(matches!(res, Err(GcError::PtrAlloc)));
         assert!(test_scope.heap.borrow().is_empty());
         let var = test_utils::make_num(1.);
-        let res = test_scope.push_var(var, Some(ptr));
+        let res = test_scope.push_var(var, Some(ptr), DeclKind::Let);
         assert!(res.is_err());
         assert!(matches!(res, Err(GcError::PtrAlloc)));
         assert!(test_scope.heap.borrow().is_empty());
@@ -276,7 +848,7 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut test_scope = Scope::new(ScopeTag::Block, &heap);
         let (x, x_ptr, x_bnd) = test_utils::make_str("x");
-        test_scope.push_var(x, Some(x_ptr)).unwrap();
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
 
         let copy = test_scope.get_var_copy(&x_bnd);
         assert!(copy.is_some());
@@ -298,7 +870,7 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
         let (x, x_ptr, x_bnd) = test_utils::make_str("x");
-        parent_scope.push_var(x, Some(x_ptr)).unwrap();
+        parent_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
         let child_scope = new_scope_as_child(parent_scope, ScopeTag::Call, &heap);
 
         let copy = child_scope.get_var_copy(&x_bnd);
@@ -310,7 +882,7 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
         let (x, x_ptr, x_bnd) = test_utils::make_str("x");
-        parent_scope.push_var(x, Some(x_ptr)).unwrap();
+        parent_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
         println!("{:?}", x_bnd);
         println!("{:?}", parent_scope.locals.get(&x_bnd));
 
@@ -328,7 +900,7 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut test_scope = Scope::new(ScopeTag::Block, &heap);
         let (x, x_ptr, x_bnd) = test_utils::make_str("x");
-        assert!(test_scope.push_var(x, Some(x_ptr)).is_ok());
+        assert!(test_scope.push_var(x, Some(x_ptr), DeclKind::Let).is_ok());
         let (update, _) = test_scope.get_var_copy(&x_bnd).unwrap();
         let update_ptr = Some(JsPtrEnum::JsStr(JsStrStruct::new("test")));
         assert!(test_scope.update_var(update, update_ptr).is_ok());
@@ -346,7 +918,7 @@ mod tests {
         let heap = test_utils::make_alloc_box();
         let mut test_scope = Scope::new(ScopeTag::Block, &heap);
         let (x, x_ptr, x_bnd) = test_utils::make_str("x");
-        assert!(test_scope.push_var(x, Some(x_ptr)).is_ok());
+        assert!(test_scope.push_var(x, Some(x_ptr), DeclKind::Let).is_ok());
         let (mut update, update_ptr) = test_scope.get_var_copy(&x_bnd).unwrap();
         let res = test_scope.update_var(update.clone(), None);
         assert!(res.is_err());
@@ -365,13 +937,13 @@ mod tests {
         let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
         {
             let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
-            test_scope.push_var(test_utils::make_num(0.), None).unwrap();
-            test_scope.push_var(test_utils::make_num(1.), None).unwrap();
-            test_scope.push_var(test_utils::make_num(2.), None).unwrap();
+            test_scope.push_var(test_utils::make_num(0.), None, DeclKind::Let).unwrap();
+            test_scope.push_var(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+            test_scope.push_var(test_utils::make_num(2.), None, DeclKind::Let).unwrap();
             let kvs = vec![(JsKey::JsSym("true".to_string()),
                             test_utils::make_num(1.), None)];
             let (var, ptr, _) = test_utils::make_obj(kvs, heap.clone());
-            test_scope.push_var(var, Some(ptr)).unwrap();
+            test_scope.push_var(var, Some(ptr), DeclKind::Let).unwrap();
             parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
         }
         assert_eq!(parent_scope.stack.len(), 1);
@@ -388,9 +960,9 @@ mod tests {
             // Push a child scope
             let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
             // Allocate some non-root variables (numbers)
-            test_scope.push_var(test_utils::make_num(0.), None).unwrap();
-            test_scope.push_var(test_utils::make_num(1.), None).unwrap();
-            test_scope.push_var(test_utils::make_num(2.), None).unwrap();
+            test_scope.push_var(test_utils::make_num(0.), None, DeclKind::Let).unwrap();
+            test_scope.push_var(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+            test_scope.push_var(test_utils::make_num(2.), None, DeclKind::Let).unwrap();
 
             // Make a string to put into an object
             // (so it's heap-allocated and we can lose its ref from the object)
@@ -404,7 +976,7 @@ mod tests {
             let (var, ptr, bnd) = test_utils::make_obj(kvs, heap.clone());
 
             // Push the obj into the current scope
-            test_scope.push_var(var, Some(ptr)).unwrap();
+            test_scope.push_var(var, Some(ptr), DeclKind::Let).unwrap();
             // The heap should now have 2 things in it: an object and a string
             assert_eq!(heap.borrow().len(), 2);
 
@@ -440,14 +1012,15 @@ mod tests {
         let fn_bnd = {
             let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
             let (var, test_fn, fn_bnd) = test_utils::make_fn(&Some("test".to_owned()), &Vec::new());
-            test_scope.push_var(test_utils::make_num(1.), None).unwrap();
-            test_scope.push_var(var, Some(test_fn)).unwrap();
+            test_scope.push_var(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+            test_scope.push_var(var, Some(test_fn), DeclKind::Let).unwrap();
             parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
             fn_bnd
         };
         assert_eq!(parent_scope.stack.len(), 0);
         assert_eq!(closures.len(), 1);
-        assert_eq!(closures[0].stack.len(), 2);
+        // The function body is empty, so only the function itself is captured
+        assert_eq!(closures[0].stack.len(), 1);
         assert_eq!(heap.borrow().len(), 1);
         assert!(heap.borrow().find_id(&fn_bnd).is_none());
         for bnd in parent_scope.stack.keys() {
@@ -455,4 +1028,434 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_named_roots() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        parent_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let mut child_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        child_scope.push_var(y, Some(y_ptr), DeclKind::Let).unwrap();
+        child_scope.push_var(test_utils::make_num(1.), None, DeclKind::Let).unwrap();
+
+        let named = child_scope.named_roots();
+        assert_eq!(named.len(), 2);
+        for &(ref local, ref unique) in &named {
+            assert!(*local == x_bnd || *local == y_bnd);
+            assert!(child_scope.roots.borrow().contains(unique));
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        parent_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let y = test_utils::make_num(1.);
+        let y_bnd = y.binding.clone();
+        test_scope.push_var(y, None, DeclKind::Let).unwrap();
+        let mut report = VerifyReport::new();
+        test_scope.verify_into(&mut report);
+        assert!(report.is_ok());
+
+        // Lose the parent's heap allocation, and the child's stack entry
+        let x_unique = test_scope.parent.as_ref().unwrap().locals[&x_bnd].clone();
+        heap.borrow_mut().free(&x_unique);
+        let y_unique = test_scope.locals[&y_bnd].clone();
+        test_scope.stack.remove(&y_unique);
+
+        let mut report = VerifyReport::new();
+        test_scope.verify_into(&mut report);
+        assert_eq!(report.violations.len(), 2);
+        assert!(report.violations.contains(&Violation::MissingAlloc(x_unique)));
+        assert!(report.violations.contains(&Violation::DanglingLocal(y_bnd, y_unique)));
+    }
+
+    #[test]
+    fn test_pop_dead_roots() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        parent_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        test_scope.push_var(y, Some(y_ptr), DeclKind::Const).unwrap();
+        let x_slot = test_scope.resolve(&x_bnd).unwrap();
+        let y_slot = test_scope.resolve(&y_bnd).unwrap();
+
+        // Lose both heap allocations, as if a collection had freed them
+        let x_unique = test_scope.parent.as_ref().unwrap().locals[&x_bnd].clone();
+        let y_unique = test_scope.locals[&y_bnd].clone();
+        heap.borrow_mut().free(&x_unique);
+        heap.borrow_mut().free(&y_unique);
+        test_scope.pop_dead_roots();
+
+        // The variables are gone from every scope, and their slots are empty
+        assert!(test_scope.roots.borrow().is_empty());
+        assert!(test_scope.parent.as_ref().unwrap().roots.borrow().is_empty());
+        assert!(test_scope.get_var(&x_bnd).is_none());
+        assert!(test_scope.get_var(&y_bnd).is_none());
+        assert!(!test_scope.is_const(&y_unique));
+        assert!(matches!(test_scope.load_slot(x_slot), Err(GcError::Slot(1, 0))));
+        assert!(matches!(test_scope.load_slot(y_slot), Err(GcError::Slot(0, 0))));
+        assert!(test_scope.slot_binding(y_slot).is_none());
+        let mut report = VerifyReport::new();
+        test_scope.verify_into(&mut report);
+        assert!(report.is_ok());
+
+        // A new variable takes a new slot
+        let z = test_utils::make_num(1.);
+        let z_bnd = z.binding.clone();
+        test_scope.push_var(z, None, DeclKind::Let).unwrap();
+        assert_eq!(test_scope.resolve(&z_bnd), Some(Slot { depth: 0, index: 1 }));
+    }
+
+    #[test]
+    fn test_transfer_stack_closure_env() {
+        let heap = test_utils::make_alloc_box();
+        let mut closures = Vec::new();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let (fn_unique, x_unique) = {
+            let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+            let body = Stmt::Ret(Exp::Var("x".to_owned()));
+            let (f, f_ptr, f_bnd) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+            let (x, x_ptr, x_bnd) = test_utils::make_named_str("x", "captured");
+            test_scope.push_var(f, Some(f_ptr), DeclKind::Let).unwrap();
+            test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+            let uniques = (test_scope.locals[&f_bnd].clone(), test_scope.locals[&x_bnd].clone());
+            parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
+            uniques
+        };
+        // Only the function is a root; the string is reachable through it
+        assert!(parent_scope.roots.borrow().contains(&fn_unique));
+        assert!(!parent_scope.roots.borrow().contains(&x_unique));
+        assert!(heap.borrow().fn_env(&fn_unique).unwrap().contains(&x_unique));
+
+        parent_scope.collect(true);
+        assert_eq!(heap.borrow().len(), 2);
+
+        // Once the function is unrooted, its environment is collected with it
+        parent_scope.roots.borrow_mut().remove(&fn_unique);
+        parent_scope.collect(true);
+        assert!(heap.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_transfer_stack_closure_precise_capture() {
+        let heap = test_utils::make_alloc_box();
+        let mut closures = Vec::new();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x_unique, y_unique) = {
+            let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+            let body = Stmt::Ret(Exp::Var("x".to_owned()));
+            let (f, f_ptr, _) = test_utils::make_fn_with_body(&None, &Vec::new(), &body);
+            let (x, x_ptr, x_bnd) = test_utils::make_named_str("x", "captured");
+            let (y, y_ptr, y_bnd) = test_utils::make_named_str("y", "not captured");
+            test_scope.push_var(f, Some(f_ptr), DeclKind::Let).unwrap();
+            test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+            test_scope.push_var(y, Some(y_ptr), DeclKind::Let).unwrap();
+            let uniques = (test_scope.locals[&x_bnd].clone(), test_scope.locals[&y_bnd].clone());
+            parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
+            uniques
+        };
+        // Only the function and the string it refers to are captured
+        assert_eq!(closures[0].stack.len(), 2);
+        assert!(closures[0].stack.contains_key(&x_unique));
+        assert!(!closures[0].stack.contains_key(&y_unique));
+        // The other string is handed to the parent scope as usual
+        assert!(parent_scope.stack.contains_key(&y_unique));
+        assert!(parent_scope.roots.borrow().contains(&y_unique));
+    }
+
+    #[test]
+    fn test_is_live_closure() {
+        let heap = test_utils::make_alloc_box();
+        let mut closures = Vec::new();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let fn_unique = {
+            let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+            let (f, f_ptr, f_bnd) = test_utils::make_fn(&None, &Vec::new());
+            test_scope.push_var(f, Some(f_ptr), DeclKind::Let).unwrap();
+            let fn_unique = test_scope.locals[&f_bnd].clone();
+            parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
+            fn_unique
+        };
+        parent_scope.collect(true);
+        assert!(closures[0].is_live_closure());
+
+        parent_scope.roots.borrow_mut().remove(&fn_unique);
+        parent_scope.collect(true);
+        assert!(!closures[0].is_live_closure());
+    }
+
+    #[test]
+    fn test_push_var_hoists_var() {
+        let heap = test_utils::make_alloc_box();
+        let parent_scope = Scope::new(ScopeTag::Call, &heap);
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Var).unwrap();
+        assert!(test_scope.locals.is_empty());
+        let parent = test_scope.parent.as_ref().unwrap();
+        let unique = parent.locals[&x_bnd].clone();
+        // Rooted by both scopes, since collections start from the innermost one
+        assert!(parent.roots.borrow().contains(&unique));
+        assert!(test_scope.roots.borrow().contains(&unique));
+    }
+
+    #[test]
+    fn test_hoist_tdz() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        test_scope.hoist(x_bnd.clone(), DeclKind::Let);
+        let (var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        assert!(test_scope.in_tdz(&var.binding));
+        assert!(matches!(test_scope.update_var(var, None), Err(GcError::Uninitialized(_))));
+
+        // Initialising the binding reuses its unique binding, ending the TDZ
+        test_scope.push_var(x, None, DeclKind::Const).unwrap();
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        assert_eq!(test_scope.stack.len(), 1);
+        assert!(!test_scope.in_tdz(&var.binding));
+        var.t = JsType::JsNum(2.);
+        assert!(matches!(test_scope.update_var(var, None), Err(GcError::ConstAssign(_))));
+    }
+
+    #[test]
+    fn test_transfer_stack_consts() {
+        let heap = test_utils::make_alloc_box();
+        let mut closures = Vec::new();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let x_unique = {
+            let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+            let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+            test_scope.push_var(x, Some(x_ptr), DeclKind::Const).unwrap();
+            test_scope.push_var(test_utils::make_num(1.), None, DeclKind::Const).unwrap();
+            let x_unique = test_scope.locals[&x_bnd].clone();
+            parent_scope = *test_scope.transfer_stack(&mut closures, false).unwrap().unwrap();
+            x_unique
+        };
+        // Only the const that outlives its scope is moved to the parent
+        assert_eq!(parent_scope.consts.len(), 1);
+        assert!(parent_scope.is_const(&x_unique));
+    }
+
+    #[test]
+    fn test_update_var_in_parent_block() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        parent_scope.push_var(x, None, DeclKind::Let).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        var.t = JsType::JsNum(2.);
+        test_scope.update_var(var, None).unwrap();
+        // The parent's variable was updated in place, not shadowed
+        assert!(test_scope.stack.is_empty());
+        let (var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        assert!(matches!(var.t, JsType::JsNum(n) if n == 2.));
+    }
+
+    #[test]
+    fn test_update_var_not_across_fn_boundary() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        parent_scope.push_var(x, None, DeclKind::Let).unwrap();
+        let (var, _) = parent_scope.get_var_copy(&x_bnd).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Call, &heap);
+        assert!(matches!(test_scope.update_var(var, None), Err(GcError::Store(..))));
+    }
+
+    #[test]
+    fn test_update_var_primitive_to_ptr() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        test_scope.push_var(x, None, DeclKind::Let).unwrap();
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        let (_, ptr, _) = test_utils::make_str("x");
+        var.t = JsType::JsPtr(JsPtrTag::JsStr);
+        test_scope.update_var(var.clone(), Some(ptr)).unwrap();
+
+        assert_eq!(heap.borrow().len(), 1);
+        assert!(test_scope.roots.borrow().contains(&var.binding));
+        assert!(matches!(test_scope.get_var_copy(&x_bnd), Some((_, Some(JsPtrEnum::JsStr(_))))));
+    }
+
+    #[test]
+    fn test_update_var_ptr_to_primitive() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        var.t = JsType::JsNum(1.);
+        test_scope.update_var(var.clone(), None).unwrap();
+
+        // The allocation is unrooted, and left for the collector
+        assert_eq!(heap.borrow().len(), 1);
+        assert!(!test_scope.roots.borrow().contains(&var.binding));
+        assert!(matches!(test_scope.get_var_copy(&x_bnd), Some((_, None))));
+        test_scope.collect(true);
+        assert!(heap.borrow().is_empty());
+        assert_eq!(heap.borrow().byte_len(), 0);
+
+        // The variable moved to a fresh binding, so it can be a pointer again
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        var.t = JsType::JsPtr(JsPtrTag::JsStr);
+        test_scope.update_var(var, Some(y_ptr)).unwrap();
+        assert!(matches!(test_scope.get_var_copy(&x_bnd), Some((_, Some(JsPtrEnum::JsStr(_))))));
+    }
+
+    #[test]
+    fn test_update_var_ptr_to_other_ptr_type() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (mut var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        let old_unique = var.binding.clone();
+        let slot = test_scope.resolve(&x_bnd).unwrap();
+        let (_, obj_ptr, _) = test_utils::make_obj(Vec::new(), heap.clone());
+        var.t = JsType::JsPtr(JsPtrTag::JsObj);
+        test_scope.update_var(var, Some(obj_ptr)).unwrap();
+
+        // The variable moved to a new allocation, in the same slot
+        let (var, ptr) = test_scope.get_var_copy(&x_bnd).unwrap();
+        assert!(matches!(ptr, Some(JsPtrEnum::JsObj(_))));
+        assert!(var.binding != old_unique);
+        assert!(test_scope.roots.borrow().contains(&var.binding));
+        assert!(!test_scope.roots.borrow().contains(&old_unique));
+        assert_eq!(test_scope.slot_binding(slot), Some(&var.binding));
+        let mut report = VerifyReport::new();
+        test_scope.verify_into(&mut report);
+        assert!(report.is_ok());
+
+        assert_eq!(heap.borrow().len(), 2);
+        test_scope.collect(true);
+        assert_eq!(heap.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_update_var_retype_keeps_shared_alloc() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (mut x, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        // An object that refers to x's current allocation
+        let (obj, obj_ptr, _) = test_utils::make_obj(vec![(JsKey::JsSym("x".to_owned()), x.clone(), None)],
+                                                     heap.clone());
+        test_scope.push_var(obj, Some(obj_ptr), DeclKind::Let).unwrap();
+
+        x.t = JsType::JsNum(1.);
+        test_scope.update_var(x.clone(), None).unwrap();
+        test_scope.collect(true);
+        assert!(heap.borrow().find_id(&x.binding).is_some());
+        let mut report = VerifyReport::new();
+        heap.borrow().verify_into(&mut report);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_update_var_ptr_same_type() {
+        let heap = test_utils::make_alloc_box();
+        let mut test_scope = Scope::new(ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Let).unwrap();
+        let (var, _) = test_scope.get_var_copy(&x_bnd).unwrap();
+        let (_, y_ptr, _) = test_utils::make_str("y");
+        test_scope.update_var(var, Some(y_ptr)).unwrap();
+
+        assert_eq!(heap.borrow().len(), 1);
+        match test_scope.get_var_copy(&x_bnd) {
+            Some((_, Some(JsPtrEnum::JsStr(ref s)))) => assert_eq!(s.text, "y"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        parent_scope.push_var(test_utils::make_num(0.), None, DeclKind::Let).unwrap();
+        parent_scope.push_var(x, None, DeclKind::Let).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let (y, y_ptr, y_bnd) = test_utils::make_str("y");
+        test_scope.push_var(y, Some(y_ptr), DeclKind::Let).unwrap();
+
+        assert_eq!(test_scope.resolve(&x_bnd), Some(Slot { depth: 1, index: 1 }));
+        assert_eq!(test_scope.resolve(&y_bnd), Some(Slot { depth: 0, index: 0 }));
+        assert_eq!(test_scope.resolve(&Binding::anon()), None);
+
+        // Function calls can't see their parent's variables
+        let call_scope = new_scope_as_child(test_scope, ScopeTag::Call, &heap);
+        assert_eq!(call_scope.resolve(&x_bnd), None);
+    }
+
+    #[test]
+    fn test_load_store_slot() {
+        let heap = test_utils::make_alloc_box();
+        let mut parent_scope = Scope::new(ScopeTag::Block, &heap);
+        let x = test_utils::make_num(1.);
+        let x_bnd = x.binding.clone();
+        parent_scope.push_var(x, None, DeclKind::Let).unwrap();
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let slot = test_scope.resolve(&x_bnd).unwrap();
+
+        let (mut var, ptr) = test_scope.load_slot(slot).unwrap();
+        assert!(matches!(var.t, JsType::JsNum(n) if n == 1.));
+        assert!(ptr.is_none());
+
+        // Storing a pointer roots it in every scope down to this one
+        let (_, s_ptr, _) = test_utils::make_str("s");
+        var.t = JsType::JsPtr(JsPtrTag::JsStr);
+        test_scope.store_slot(slot, var, Some(s_ptr)).unwrap();
+        let (var, ptr) = test_scope.load_slot(slot).unwrap();
+        assert!(matches!(ptr, Some(JsPtrEnum::JsStr(_))));
+        assert!(test_scope.roots.borrow().contains(&var.binding));
+        assert!(test_scope.parent.as_ref().unwrap().roots.borrow().contains(&var.binding));
+        // The slot path and the name path agree
+        assert_eq!(test_scope.get_var_copy(&x_bnd).unwrap().0.binding, var.binding);
+
+        assert!(matches!(test_scope.load_slot(Slot { depth: 2, index: 0 }), Err(GcError::Slot(2, 0))));
+    }
+
+    #[test]
+    fn test_collect_remaps_compacted() {
+        let heap = Rc::new(RefCell::new(AllocBox::with_config(GcConfig {
+            compact: true,
+            ..GcConfig::default()
+        })));
+        let parent_scope = Scope::new(ScopeTag::Call, &heap);
+        let mut test_scope = new_scope_as_child(parent_scope, ScopeTag::Block, &heap);
+        let (x, x_ptr, x_bnd) = test_utils::make_str("x");
+        test_scope.push_var(x, Some(x_ptr), DeclKind::Const).unwrap();
+        let old_unique = test_scope.locals[&x_bnd].clone();
+
+        test_scope.collect(true);
+        let new_unique = test_scope.locals[&x_bnd].clone();
+        assert!(new_unique != old_unique);
+        assert!(test_scope.roots.borrow().contains(&new_unique));
+        assert!(!test_scope.roots.borrow().contains(&old_unique));
+        assert!(test_scope.is_const(&new_unique));
+        assert_eq!(test_scope.frame[0].binding, new_unique);
+        let (var, ptr) = test_scope.get_var_copy(&x_bnd).unwrap();
+        assert_eq!(var.binding, new_unique);
+        assert!(matches!(ptr, Some(JsPtrEnum::JsStr(_))));
+        assert!(test_scope.resolve(&x_bnd).map(|slot| test_scope.load_slot(slot).is_ok()).unwrap());
+    }
 }
@@ -27,9 +27,20 @@ pub fn make_obj(kvs: Vec<(JsKey, JsVar, Option<JsPtrEnum>)>, heap: Rc<RefCell<Al
 }
 
 pub fn make_fn(name: &Option<String>, params: &Vec<String>) -> (JsVar, JsPtrEnum, Binding) {
+    make_fn_with_body(name, params, &Stmt::Empty)
+}
+
+pub fn make_fn_with_body(name: &Option<String>, params: &Vec<String>, body: &Stmt) -> (JsVar, JsPtrEnum, Binding) {
     let var = JsVar::new(JsType::JsPtr(JsPtrTag::JsFn));
     let bnd = var.binding.clone();
-    (var, JsPtrEnum::JsFn(JsFnStruct::new(name, params, &Stmt::Empty)), bnd)
+    (var, JsPtrEnum::JsFn(JsFnStruct::new(name, params, body)), bnd)
+}
+
+pub fn make_named_str(name: &str, s: &str) -> (JsVar, JsPtrEnum, Binding) {
+    let (mut var, ptr, _) = make_str(s);
+    var.binding = Binding::new(name.to_owned());
+    let bnd = var.binding.clone();
+    (var, ptr, bnd)
 }
 
 pub fn make_alloc_box() -> Rc<RefCell<AllocBox>> {
@@ -0,0 +1,63 @@
+use std::fmt;
+
+use js_types::binding::Binding;
+
+/// A broken heap or scope invariant, found by `ScopeManager::verify`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// A pointer variable on a scope's stack has no heap allocation.
+    MissingAlloc(Binding),
+    /// A pointer variable's type doesn't match the type of its heap allocation.
+    TagMismatch(Binding),
+    /// A local binding maps to a unique binding that isn't on the stack.
+    DanglingLocal(Binding, Binding),
+    /// A binding is in more than one of the collector's color sets.
+    MultipleColors(Binding),
+    /// A black object points to a white one, breaking the tri-color invariant.
+    BlackToWhite(Binding, Binding),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::MissingAlloc(ref bnd) => write!(f, "Pointer {} has no heap allocation", bnd),
+            Violation::TagMismatch(ref bnd) => write!(f, "Pointer {} does not match the type of its allocation", bnd),
+            Violation::DanglingLocal(ref local, ref unique) =>
+                write!(f, "Local {} maps to {}, which is not on the stack", local, unique),
+            Violation::MultipleColors(ref bnd) => write!(f, "Binding {} is in more than one color set", bnd),
+            Violation::BlackToWhite(ref parent, ref child) =>
+                write!(f, "Black object {} points to white object {}", parent, child),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub violations: Vec<Violation>,
+}
+
+impl VerifyReport {
+    pub fn new() -> VerifyReport {
+        VerifyReport::default()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn push(&mut self, violation: Violation) {
+        self.violations.push(violation);
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "No violations");
+        }
+        for violation in &self.violations {
+            try!(writeln!(f, "{}", violation));
+        }
+        Ok(())
+    }
+}